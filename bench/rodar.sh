@@ -0,0 +1,10 @@
+#!/bin/sh
+# Mede o tempo de cada programa desta pasta: ./bench/rodar.sh [caminho/do/fe] [opções]
+FE=${1:-target/release/fe}
+shift 2>/dev/null
+for programa in "$(dirname "$0")"/*.fe; do
+    inicio=$(date +%s%N)
+    resultado=$("$FE" "$@" "$programa")
+    fim=$(date +%s%N)
+    printf "%-16s %6d ms  %s\n" "$(basename "$programa")" $(( (fim - inicio) / 1000000 )) "$resultado"
+done
@@ -0,0 +1,233 @@
+use crate::diagnostic::RuntimeError;
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+/// Code of the diagnostic of a program stopped by its limits
+pub const CODE: &str = "E002";
+
+/// Exit code of a program stopped by its limits, the same as the `timeout` command
+pub const EXIT_CODE: i32 = 124;
+
+// The clock is read once every this many steps
+const CHECK_EVERY: u64 = 1024;
+
+/// How much work of a native function, such as bytes made or divisors tried, counts as one
+/// step of the program
+pub const WORK_PER_STEP: u64 = 1024;
+
+/// How much a program may run before it is stopped. Graders use it so a student's endless
+/// loop doesn't hang them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    // Statements run by the interpreter, or instructions run by the VM, plus the work of long
+    // native calls
+    pub steps: Option<u64>,
+    pub time: Option<Duration>,
+    // Bytes written by saida and limpar
+    pub output: Option<usize>,
+}
+
+struct Budget {
+    limits: Limits,
+    start: Instant,
+    // Steps run up to the last check
+    steps: u64,
+    // Steps allowed between the last check and the next
+    granted: u64,
+    output: usize,
+}
+
+thread_local! {
+    static BUDGET: RefCell<Budget> = RefCell::new(Budget {
+        limits: Limits::default(),
+        start: Instant::now(),
+        steps: 0,
+        granted: 0,
+        output: 0,
+    });
+    // Steps left before the limits are checked again, so most steps only count down
+    static COUNTDOWN: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Starts counting from zero, with the limits of the program about to run
+pub fn start(limits: Limits) {
+    BUDGET.with(|budget| {
+        *budget.borrow_mut() = Budget {
+            limits,
+            start: Instant::now(),
+            steps: 0,
+            granted: 0,
+            output: 0,
+        }
+    });
+    COUNTDOWN.with(|countdown| countdown.set(0));
+}
+
+/// Counts one step of the program, failing when it goes over its steps or time
+pub fn step() -> Result<(), RuntimeError> {
+    let left = COUNTDOWN.with(Cell::get);
+    if left == 0 {
+        return check();
+    }
+    COUNTDOWN.with(|countdown| countdown.set(left - 1));
+    Ok(())
+}
+
+/// Counts `steps` steps at once, for a native function in the middle of long work, so the
+/// limits stop it without waiting for it to return
+pub fn charge(steps: u64) -> Result<(), RuntimeError> {
+    let left = COUNTDOWN.with(Cell::get);
+    if steps <= left {
+        COUNTDOWN.with(|countdown| countdown.set(left - steps));
+        return Ok(());
+    }
+    // The check counts the steps left and one more itself
+    BUDGET.with(|budget| budget.borrow_mut().steps += steps - left - 1);
+    COUNTDOWN.with(|countdown| countdown.set(0));
+    check()
+}
+
+fn check() -> Result<(), RuntimeError> {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        // The steps granted at the last check were all used, and this is one more
+        budget.steps += budget.granted;
+        let mut grant = CHECK_EVERY;
+        if let Some(max) = budget.limits.steps {
+            if budget.steps >= max {
+                return Err(exceeded(format!("o programa passou de {max} passos")));
+            }
+            grant = grant.min(max - budget.steps - 1);
+        }
+        if let Some(time) = budget.limits.time {
+            if budget.start.elapsed() > time {
+                let seconds = time.as_secs_f64();
+                return Err(exceeded(format!("o programa passou de {seconds} segundos")));
+            }
+        }
+        budget.steps += 1;
+        budget.granted = grant;
+        COUNTDOWN.with(|countdown| countdown.set(grant));
+        Ok(())
+    })
+}
+
+/// Counts the bytes of text about to be written, failing instead when they would go over the
+/// output limit
+pub fn output(bytes: usize) -> Result<(), RuntimeError> {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let output = budget.output + bytes;
+        if let Some(max) = budget.limits.output {
+            if output > max {
+                return Err(exceeded(format!("a saída passou de {max} bytes")));
+            }
+        }
+        budget.output = output;
+        Ok(())
+    })
+}
+
+fn exceeded(what: String) -> RuntimeError {
+    RuntimeError {
+        code: CODE,
+        ..RuntimeError::new(format!("Limite de execução: {what}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_after_the_allowed_steps() {
+        start(Limits {
+            steps: Some(3000),
+            ..Limits::default()
+        });
+        for _ in 0..3000 {
+            step().unwrap();
+        }
+        let error = step().unwrap_err();
+        assert_eq!(error.code, CODE);
+        assert_eq!(error.message, "Limite de execução: o programa passou de 3000 passos");
+    }
+
+    #[test]
+    fn charges_many_steps_at_once() {
+        start(Limits {
+            steps: Some(3000),
+            ..Limits::default()
+        });
+        charge(1500).unwrap();
+        charge(1000).unwrap();
+        for _ in 0..500 {
+            step().unwrap();
+        }
+        assert!(step().is_err());
+
+        start(Limits {
+            steps: Some(3000),
+            ..Limits::default()
+        });
+        let error = charge(5000).unwrap_err();
+        assert_eq!(error.message, "Limite de execução: o programa passou de 3000 passos");
+    }
+
+    #[test]
+    fn stops_when_the_output_would_go_over() {
+        start(Limits {
+            output: Some(10),
+            ..Limits::default()
+        });
+        output(6).unwrap();
+        assert_eq!(
+            output(6).unwrap_err().message,
+            "Limite de execução: a saída passou de 10 bytes"
+        );
+        start(Limits::default());
+        assert!(output(6).is_ok());
+    }
+
+    #[test]
+    fn stops_after_the_allowed_time() {
+        start(Limits {
+            time: Some(Duration::from_millis(20)),
+            ..Limits::default()
+        });
+        let begin = Instant::now();
+        let error = loop {
+            if let Err(error) = step() {
+                break error.message;
+            }
+        };
+        assert!(begin.elapsed() >= Duration::from_millis(20));
+        assert_eq!(error, "Limite de execução: o programa passou de 0.02 segundos");
+    }
+
+    #[test]
+    fn stops_long_native_calls() {
+        use crate::interpreter::Interpreter;
+        use crate::parser::Parser;
+        use crate::resolver::Resolver;
+        use crate::scanner::Scanner;
+
+        // Each is a single step of the program, but far more work than the limit allows
+        for source in [
+            "var s = \"a\".repetir(100000000);",
+            "var primo = mat.eh_primo(9007199254740881);",
+        ] {
+            let tokens = Scanner::new(source).scan_tokens().unwrap();
+            let stmts = Parser::new(tokens).parse().unwrap();
+            let locals = Resolver::new().resolve(&stmts.iter().collect()).unwrap();
+            let mut interpreter = Interpreter::new().with_limits(Limits {
+                steps: Some(1000),
+                ..Limits::default()
+            });
+            interpreter.resolve(locals);
+
+            let error = interpreter.run(&stmts).unwrap_err();
+            assert_eq!(error.message, "Limite de execução: o programa passou de 1000 passos", "{source}");
+        }
+    }
+}
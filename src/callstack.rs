@@ -0,0 +1,183 @@
+use crate::diagnostic::RuntimeError;
+use crate::expr::{CallableImpl, LiteralValue, LoxFunctionImpl};
+use std::cell::{Cell, RefCell};
+
+/// How many calls can be in progress at once, unless the program is run with another limit.
+/// A call of the tree-walker takes up to some hundreds of KB of the native stack in debug
+/// builds, and main gives the program a thread with room for twice this depth.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+// Traces longer than this keep only their ends
+const TRACE_ENDS: usize = 10;
+
+/// A call in progress: what was called, and the line of the call
+#[derive(Clone)]
+struct Call {
+    name: String,
+    line: usize,
+}
+
+thread_local! {
+    static CALLS: RefCell<Vec<Call>> = const { RefCell::new(vec![]) };
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_DEPTH) };
+    // The calls that were in progress where the error being propagated was raised
+    static FAILED: RefCell<Option<Vec<Call>>> = const { RefCell::new(None) };
+}
+
+/// Starts a call, or fails with a stack overflow when too many are already in progress
+pub fn enter(name: String, line: usize) -> Result<(), RuntimeError> {
+    let max_depth = MAX_DEPTH.with(Cell::get);
+    if depth() >= max_depth {
+        return Err(RuntimeError::new(format!(
+            "Estouro de pilha: a chamada de '{name}' passou do limite de {max_depth} chamadas em andamento"
+        )));
+    }
+    CALLS.with(|calls| calls.borrow_mut().push(Call { name, line }));
+    Ok(())
+}
+
+pub fn set_max_depth(max_depth: usize) {
+    MAX_DEPTH.with(|max| max.set(max_depth));
+}
+
+pub fn leave() {
+    CALLS.with(|calls| calls.borrow_mut().pop());
+}
+
+pub fn depth() -> usize {
+    CALLS.with(|calls| calls.borrow().len())
+}
+
+/// Drops the calls above `depth`, left behind by an error
+pub fn unwind_to(depth: usize) {
+    CALLS.with(|calls| calls.borrow_mut().truncate(depth));
+}
+
+/// Records the calls in progress, unless a deeper point of the same error already did
+pub fn fail() {
+    FAILED.with(|failed| {
+        let mut failed = failed.borrow_mut();
+        if failed.is_none() {
+            *failed = Some(CALLS.with(|calls| calls.borrow().clone()));
+        }
+    });
+}
+
+/// Runs a call, keeping it on the stack while it runs
+pub fn traced<T>(
+    name: String,
+    line: usize,
+    call: impl FnOnce() -> Result<T, RuntimeError>,
+) -> Result<T, RuntimeError> {
+    if let Err(msg) = enter(name, line) {
+        fail();
+        return Err(msg);
+    }
+    let result = call();
+    if result.is_err() {
+        fail();
+    }
+    leave();
+    result
+}
+
+/// Forgets everything, before running a new program
+pub fn reset() {
+    CALLS.with(|calls| calls.borrow_mut().clear());
+    FAILED.with(|failed| failed.borrow_mut().take());
+}
+
+/// The message of an uncaught error followed by where it happened:
+/// "em calcular (linha 12) chamado por principal (linha 30)"
+pub fn with_trace(msg: String) -> String {
+    let calls = FAILED.with(|failed| failed.borrow_mut().take());
+    reset();
+    match calls {
+        Some(calls) if !calls.is_empty() => format!("{msg}\nem {}", trace(&calls)),
+        _ => msg,
+    }
+}
+
+/// The calls from the innermost, with repeated calls (a recursion) written once
+fn trace(calls: &[Call]) -> String {
+    let mut entries: Vec<(&Call, usize)> = vec![];
+    for call in calls.iter().rev() {
+        match entries.last_mut() {
+            Some((last, times)) if last.name == call.name && last.line == call.line => *times += 1,
+            _ => entries.push((call, 1)),
+        }
+    }
+    let mut names: Vec<String> = entries
+        .iter()
+        .map(|(call, times)| match times {
+            1 => format!("{} (linha {})", call.name, call.line),
+            _ => format!("{} (linha {}, {times} vezes)", call.name, call.line),
+        })
+        .collect();
+
+    if entries.len() > 2 * TRACE_ENDS {
+        let middle = TRACE_ENDS..entries.len() - TRACE_ENDS;
+        let hidden: usize = entries[middle].iter().map(|(_, times)| times).sum();
+        names.splice(
+            TRACE_ENDS..names.len() - TRACE_ENDS,
+            [format!("... ({hidden} chamadas omitidas)")],
+        );
+    }
+    names.join(" chamado por ")
+}
+
+/// How a function is named in the trace, with its class when it is a method
+pub fn function_name(fun: &LoxFunctionImpl) -> String {
+    match &fun.class_name {
+        Some(class_name) => format!("{class_name}.{}", fun.name),
+        None => fun.name.clone(),
+    }
+}
+
+/// How a called value is named in the trace
+pub fn call_name(callable: &LiteralValue) -> String {
+    match callable {
+        LiteralValue::Callable(CallableImpl::LoxFunction(fun)) => function_name(fun),
+        LiteralValue::Callable(CallableImpl::NativeFunction(fun)) => fun.name.clone(),
+        LiteralValue::Callable(CallableImpl::Compiled(closure)) => closure.proto.name.clone(),
+        LiteralValue::LoxClass { name, .. } => format!("{name}.ini"),
+        other => other.to_type().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_innermost_call_first() {
+        reset();
+        let result: Result<(), RuntimeError> = traced("principal".to_string(), 30, || {
+            traced("calcular".to_string(), 12, || Err(RuntimeError::new("falhou")))
+        });
+        assert_eq!(depth(), 0);
+        assert_eq!(
+            with_trace(result.unwrap_err().message),
+            "falhou\nem calcular (linha 12) chamado por principal (linha 30)"
+        );
+        // The trace is consumed
+        assert_eq!(with_trace("outro".to_string()), "outro");
+    }
+
+    #[test]
+    fn stops_a_runaway_recursion() {
+        fn recurse() -> Result<(), RuntimeError> {
+            traced("fatorial".to_string(), 3, recurse)
+        }
+        reset();
+        set_max_depth(50);
+        let result = recurse();
+        set_max_depth(DEFAULT_MAX_DEPTH);
+        assert_eq!(depth(), 0);
+        assert_eq!(
+            with_trace(result.unwrap_err().message),
+            "Estouro de pilha: a chamada de 'fatorial' passou do limite de 50 chamadas em andamento\n\
+             em fatorial (linha 3, 50 vezes)"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use crate::diagnostic::Span;
+use crate::expr::{Expr, LiteralValue};
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+
+/// One VM instruction. Operands are indexes into the pools of the `Chunk` or stack slots
+/// relative to the start of the current call; jump targets are positions in `code`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    Dup,
+    GetLocal(u16),
+    SetLocal(u16),
+    GetUpvalue(u16),
+    SetUpvalue(u16),
+    GetGlobal(u16),
+    SetGlobal(u16),
+    DefineGlobal(u16),
+    // Operand: the token of the name, for messages
+    GetProperty(u16),
+    SetProperty(u16),
+    // Operands: the bracket token and how many indices
+    GetIndex(u16, u8),
+    SetIndex(u16, u8),
+    // Operand: the operator token
+    Binary(u16),
+    Unary(u16),
+    Jump(u32),
+    JumpIfFalse(u32),
+    // `e` leaves falso and jumps when the left side is false, `ou` leaves it when true
+    AndJump(u32),
+    OrJump(u32),
+    Loop(u32),
+    Call(u8),
+    Closure(u16),
+    Return,
+    Print,
+    Limpar,
+    Tuple(u16),
+    Destructure(u16),
+    // Operands: name token, number of dimensions, element type token
+    MakeArray(u16, u8, u16),
+    // Operands: operator token, whether a step was given, inclusive
+    MakeRange(u16, bool, bool),
+    // Slot of the iterated value, followed by the position and the loop variable;
+    // jumps to the operand when the iteration is over
+    IterNext(u16, u32),
+    // Declarations the VM leaves to the tree-walking interpreter (classes, generators...)
+    TreeStmt(u16),
+    TreeExpr(u16),
+}
+
+/// Bytecode of one function and the pools its instructions refer to
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<LiteralValue>,
+    pub names: Vec<String>,
+    pub tokens: Vec<Token>,
+    pub functions: Vec<std::rc::Rc<FunctionProto>>,
+    pub statements: Vec<Stmt>,
+    pub expressions: Vec<Expr>,
+}
+
+/// Where a closure finds a captured variable when it is created: a local of the enclosing
+/// function, or one of the enclosing function's own upvalues
+#[derive(Debug, Clone, Copy)]
+pub struct Capture {
+    pub is_local: bool,
+    pub index: u16,
+}
+
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: Op, span: Span) -> usize {
+        self.code.push(op);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: LiteralValue) -> Result<u16, String> {
+        self.constants.push(value);
+        pool_index(self.constants.len())
+    }
+
+    pub fn add_name(&mut self, name: &str) -> Result<u16, String> {
+        if let Some(i) = self.names.iter().position(|n| n == name) {
+            return pool_index(i + 1);
+        }
+        self.names.push(name.to_string());
+        pool_index(self.names.len())
+    }
+
+    pub fn add_token(&mut self, token: &Token) -> Result<u16, String> {
+        self.tokens.push(token.clone());
+        pool_index(self.tokens.len())
+    }
+
+    /// Readable listing of the instructions, one per line
+    #[allow(dead_code)]
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut listing = format!("== {name} ==\n");
+        for (i, op) in self.code.iter().enumerate() {
+            listing.push_str(&format!("{i:04} linha {:>3}  {op:?}\n", self.spans[i].line));
+        }
+        listing
+    }
+}
+
+/// Index of the last element of a pool of `len` elements, if it fits in an operand
+fn pool_index(len: usize) -> Result<u16, String> {
+    u16::try_from(len - 1).map_err(|_| "A função é grande demais para a VM".to_string())
+}
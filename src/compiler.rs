@@ -0,0 +1,645 @@
+use crate::chunk::{Capture, Chunk, FunctionProto, Op};
+use crate::diagnostic::{RuntimeError, Span};
+use crate::expr::{Expr, LiteralValue};
+use crate::generator::contains_yield;
+use crate::scanner::{Token, TokenType};
+use crate::stmt::Stmt;
+use std::rc::Rc;
+
+struct Local {
+    name: String,
+    depth: usize,
+}
+
+/// A function whose bytecode is being written; the script itself is the outermost one
+struct FunctionState {
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    captures: Vec<Capture>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            chunk: Chunk::default(),
+            // Slot 0 holds the function being called
+            locals: vec![Local {
+                name: String::new(),
+                depth: 0,
+            }],
+            captures: vec![],
+            scope_depth: 0,
+        }
+    }
+}
+
+/// Translates the resolved syntax tree into bytecode for the VM.
+///
+/// Classes, mixins, contracts, command functions and generators are not compiled: at the top
+/// level of the program they are handed over to the tree-walking interpreter, which defines
+/// them in the shared globals. A program declaring one anywhere else is left to the
+/// tree-walking interpreter entirely.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    // Where the instructions being written come from
+    span: Span,
+    // Whether a declaration only the tree-walker runs was found inside a function or block
+    tree_only: bool,
+}
+
+impl Compiler {
+    /// The bytecode of the program, or `None` when only the tree-walker can run it
+    pub fn compile(stmts: &[Stmt]) -> Result<Option<FunctionProto>, RuntimeError> {
+        let mut compiler = Compiler {
+            functions: vec![FunctionState::new("programa", 0)],
+            span: Span::default(),
+            tree_only: false,
+        };
+        for stmt in stmts {
+            compiler.statement(stmt)?;
+        }
+        if compiler.tree_only {
+            return Ok(None);
+        }
+        compiler.emit(Op::Nil);
+        compiler.emit(Op::Return);
+
+        let state = compiler
+            .functions
+            .pop()
+            .expect("O programa sempre tem uma função");
+        Ok(Some(FunctionProto {
+            name: state.name,
+            arity: state.arity,
+            chunk: state.chunk,
+            captures: state.captures,
+        }))
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("Não há função sendo compilada")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let span = self.span;
+        self.chunk().write(op, span)
+    }
+
+    fn position(&mut self) -> u32 {
+        self.chunk().code.len() as u32
+    }
+
+    /// Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.position();
+        let chunk = self.chunk();
+        chunk.code[at] = match chunk.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::AndJump(_) => Op::AndJump(target),
+            Op::OrJump(_) => Op::OrJump(target),
+            Op::IterNext(slot, _) => Op::IterNext(slot, target),
+            other => other,
+        };
+    }
+
+    fn token(&mut self, token: &Token) -> Result<u16, RuntimeError> {
+        self.span = Span::of(token);
+        Ok(self.chunk().add_token(token)?)
+    }
+
+    /// A condition that is neither true nor false is reported at the condition, like in the
+    /// interpreter
+    fn at_condition(&mut self, condition: &Expr) {
+        self.span = condition.token().map(Span::of).unwrap_or_default();
+    }
+
+    fn is_top_level(&self) -> bool {
+        self.functions.len() == 1 && self.functions[0].scope_depth == 0
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let mut count = 0;
+        while state.locals.last().is_some_and(|local| local.depth > depth) {
+            state.locals.pop();
+            count += 1;
+        }
+        for _ in 0..count {
+            self.emit(Op::Pop);
+        }
+    }
+
+    fn add_local(&mut self, name: &str) -> Result<u16, RuntimeError> {
+        let state = self.current();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth,
+        });
+        u16::try_from(state.locals.len() - 1)
+            .map_err(|_| RuntimeError::new("Variáveis locais demais para a VM"))
+    }
+
+    /// Declares the value on top of the stack: a local inside a scope, a global otherwise
+    fn define(&mut self, name: &str) -> Result<(), RuntimeError> {
+        if self.current().scope_depth > 0 {
+            self.add_local(name)?;
+        } else {
+            let index = self.chunk().add_name(name)?;
+            self.emit(Op::DefineGlobal(index));
+        }
+        Ok(())
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u16> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u16)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Result<Option<u16>, RuntimeError> {
+        if function == 0 {
+            return Ok(None);
+        }
+        let capture = if let Some(slot) = self.resolve_local(function - 1, name) {
+            Capture {
+                is_local: true,
+                index: slot,
+            }
+        } else if let Some(index) = self.resolve_upvalue(function - 1, name)? {
+            Capture {
+                is_local: false,
+                index,
+            }
+        } else {
+            return Ok(None);
+        };
+
+        let captures = &mut self.functions[function].captures;
+        if let Some(existing) = captures
+            .iter()
+            .position(|c| c.is_local == capture.is_local && c.index == capture.index)
+        {
+            return Ok(Some(existing as u16));
+        }
+        captures.push(capture);
+        u16::try_from(captures.len() - 1)
+            .map(Some)
+            .map_err(|_| RuntimeError::new("Variáveis capturadas demais para a VM"))
+    }
+
+    fn get_variable(&mut self, name: &Token) -> Result<(), RuntimeError> {
+        self.span = Span::of(name);
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, &name.lexeme) {
+            self.emit(Op::GetLocal(slot));
+        } else if let Some(index) = self.resolve_upvalue(function, &name.lexeme)? {
+            self.emit(Op::GetUpvalue(index));
+        } else {
+            let index = self.chunk().add_name(&name.lexeme)?;
+            self.emit(Op::GetGlobal(index));
+        }
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &Token) -> Result<(), RuntimeError> {
+        self.span = Span::of(name);
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, &name.lexeme) {
+            self.emit(Op::SetLocal(slot));
+        } else if let Some(index) = self.resolve_upvalue(function, &name.lexeme)? {
+            self.emit(Op::SetUpvalue(index));
+        } else {
+            let index = self.chunk().add_name(&name.lexeme)?;
+            self.emit(Op::SetGlobal(index));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.expression(expression)?;
+                self.emit(Op::Pop);
+            }
+            Stmt::Print { expression } => {
+                self.expression(expression)?;
+                self.emit(Op::Print);
+            }
+            Stmt::Limpar { expression } => {
+                self.expression(expression)?;
+                self.emit(Op::Limpar);
+            }
+            Stmt::Var { name, initializer } => {
+                self.expression(initializer)?;
+                self.span = Span::of(name);
+                self.define(&name.lexeme)?;
+            }
+            Stmt::VarTuple { names, initializer } => {
+                self.expression(initializer)?;
+                self.emit(Op::Destructure(names.len() as u16));
+                if self.current().scope_depth > 0 {
+                    for name in names {
+                        self.add_local(&name.lexeme)?;
+                    }
+                } else {
+                    // The last value is on top of the stack
+                    for name in names.iter().rev() {
+                        self.define(&name.lexeme)?;
+                    }
+                }
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+            }
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => {
+                self.expression(predicate)?;
+                self.at_condition(predicate);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.statement(then)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                if let Some(els) = els {
+                    self.statement(els)?;
+                }
+                self.patch(to_end);
+            }
+            Stmt::WhileStmt { condition, body } => {
+                let start = self.position();
+                self.expression(condition)?;
+                self.at_condition(condition);
+                let to_exit = self.emit(Op::JumpIfFalse(0));
+                self.statement(body)?;
+                self.emit(Op::Loop(start));
+                self.patch(to_exit);
+            }
+            Stmt::ForRange {
+                variable,
+                range,
+                body,
+            } => {
+                self.begin_scope();
+                self.expression(range)?;
+                let base = self.add_local("(percorrido)")?;
+                let zero = self.chunk().add_constant(LiteralValue::Number(0.0))?;
+                self.emit(Op::Constant(zero));
+                self.add_local("(posicao)")?;
+                self.emit(Op::Nil);
+                self.add_local(&variable.lexeme)?;
+
+                let start = self.position();
+                self.span = Span::of(variable);
+                let to_exit = self.emit(Op::IterNext(base, 0));
+                self.statement(body)?;
+                self.emit(Op::Loop(start));
+                self.patch(to_exit);
+                self.end_scope();
+            }
+            Stmt::Function { name, params, body } => {
+                self.span = Span::of(name);
+                if contains_yield(body) {
+                    return self.delegate(stmt);
+                }
+                if self.current().scope_depth > 0 {
+                    // Declared before the body so the function can call itself
+                    self.add_local(&name.lexeme)?;
+                    self.function(&name.lexeme, params, body)?;
+                } else {
+                    self.function(&name.lexeme, params, body)?;
+                    self.define(&name.lexeme)?;
+                }
+            }
+            Stmt::ReturnStmt { keyword, value } => {
+                self.span = Span::of(keyword);
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            Stmt::Yield { keyword, .. } => {
+                return Err(RuntimeError::new("'produz' fora de um gerador").raised_at(Span::of(keyword)));
+            }
+            Stmt::Class { name, .. } | Stmt::Mixin { name, .. } | Stmt::Contract { name, .. } => {
+                self.span = Span::of(name);
+                self.delegate(stmt)?;
+            }
+            Stmt::CmdFunction { name, .. } => {
+                self.span = Span::of(name);
+                self.delegate(stmt)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves a top-level declaration to the tree-walking interpreter, and a nested one the
+    /// whole program
+    fn delegate(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        if !self.is_top_level() {
+            self.tree_only = true;
+            return Ok(());
+        }
+        self.chunk().statements.push(stmt.clone());
+        let index = self.chunk().statements.len() - 1;
+        self.emit(Op::TreeStmt(index as u16));
+        Ok(())
+    }
+
+    /// Compiles a function body and leaves the closure on the stack
+    fn function(&mut self, name: &str, params: &[Token], body: &[Stmt]) -> Result<(), RuntimeError> {
+        let mut state = FunctionState::new(name, params.len());
+        state.scope_depth = 1;
+        self.functions.push(state);
+        for param in params {
+            self.add_local(&param.lexeme)?;
+        }
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        self.emit(Op::Nil);
+        self.emit(Op::Return);
+
+        let state = self
+            .functions
+            .pop()
+            .expect("A função acabou de ser compilada");
+        let proto = FunctionProto {
+            name: state.name,
+            arity: state.arity,
+            chunk: state.chunk,
+            captures: state.captures,
+        };
+        self.chunk().functions.push(Rc::new(proto));
+        let index = self.chunk().functions.len() - 1;
+        self.emit(Op::Closure(index as u16));
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), RuntimeError> {
+        match expr {
+            Expr::AnonFunction {
+                id: _,
+                paren,
+                arguments,
+                body,
+            } => {
+                self.span = Span::of(paren);
+                if contains_yield(body) {
+                    if !self.is_top_level() {
+                        self.tree_only = true;
+                        return Ok(());
+                    }
+                    self.chunk().expressions.push(expr.clone());
+                    let index = self.chunk().expressions.len() - 1;
+                    self.emit(Op::TreeExpr(index as u16));
+                } else {
+                    self.function("fun_anonima", arguments, body)?;
+                }
+            }
+            Expr::Array {
+                id: _,
+                name,
+                bounds,
+                element_type,
+            } => {
+                for (lower, upper) in bounds {
+                    self.expression(lower)?;
+                    self.expression(upper)?;
+                }
+                let element_type = self.token(element_type)?;
+                let name = self.token(name)?;
+                self.emit(Op::MakeArray(name, bounds.len() as u8, element_type));
+            }
+            Expr::Assign { id: _, name, value } => {
+                self.expression(value)?;
+                self.set_variable(name)?;
+            }
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => {
+                // The tuple itself stays on the stack as the value of the expression
+                self.expression(value)?;
+                self.emit(Op::Dup);
+                self.emit(Op::Destructure(targets.len() as u16));
+                for target in targets.iter().rev() {
+                    if let Expr::Variable { id: _, name } = target {
+                        self.set_variable(name)?;
+                    }
+                    self.emit(Op::Pop);
+                }
+            }
+            Expr::Binary {
+                id: _,
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                let operator = self.token(operator)?;
+                self.emit(Op::Binary(operator));
+            }
+            Expr::Call {
+                id: _,
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expression(callee)?;
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+                // Errors in the call point at what was called, like in the interpreter
+                self.span = Span::of(expr.token().unwrap_or(paren));
+                let count = u8::try_from(arguments.len())
+                    .map_err(|_| "Argumentos demais para a VM".to_string())?;
+                self.emit(Op::Call(count));
+            }
+            Expr::Get {
+                id: _,
+                object,
+                name,
+            } => {
+                self.expression(object)?;
+                let name = self.token(name)?;
+                self.emit(Op::GetProperty(name));
+            }
+            Expr::Grouping { id: _, expression } => self.expression(expression)?,
+            Expr::Index {
+                id: _,
+                object,
+                bracket,
+                indices,
+            } => {
+                self.expression(object)?;
+                for index in indices {
+                    self.expression(index)?;
+                }
+                let bracket = self.token(bracket)?;
+                self.emit(Op::GetIndex(bracket, indices.len() as u8));
+            }
+            Expr::Literal { id: _, value } => {
+                match value {
+                    LiteralValue::Nil => self.emit(Op::Nil),
+                    LiteralValue::True => self.emit(Op::True),
+                    LiteralValue::False => self.emit(Op::False),
+                    other => {
+                        let index = self.chunk().add_constant(other.clone())?;
+                        self.emit(Op::Constant(index))
+                    }
+                };
+            }
+            Expr::Logical {
+                id: _,
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.span = Span::of(operator);
+                let jump = match operator.token_type {
+                    TokenType::Or => self.emit(Op::OrJump(0)),
+                    _ => self.emit(Op::AndJump(0)),
+                };
+                self.expression(right)?;
+                self.patch(jump);
+            }
+            Expr::Set {
+                id: _,
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                let name = self.token(name)?;
+                self.emit(Op::SetProperty(name));
+            }
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket,
+                indices,
+                value,
+            } => {
+                self.expression(object)?;
+                for index in indices {
+                    self.expression(index)?;
+                }
+                self.expression(value)?;
+                let bracket = self.token(bracket)?;
+                self.emit(Op::SetIndex(bracket, indices.len() as u8));
+            }
+            Expr::This { id: _, keyword } | Expr::Super { id: _, keyword, .. } => {
+                return Err(RuntimeError::new(format!("'{}' só pode ser usado dentro de uma classe", keyword.lexeme))
+                    .raised_at(Span::of(keyword)));
+            }
+            Expr::Tuple { id: _, elements } => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.emit(Op::Tuple(elements.len() as u16));
+            }
+            Expr::Range {
+                id: _,
+                start,
+                operator,
+                end,
+                step,
+                inclusive,
+            } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                if let Some(step) = step {
+                    self.expression(step)?;
+                }
+                let operator = self.token(operator)?;
+                self.emit(Op::MakeRange(operator, step.is_some(), *inclusive));
+            }
+            Expr::Unary {
+                id: _,
+                operator,
+                right,
+            } => {
+                self.expression(right)?;
+                let operator = self.token(operator)?;
+                self.emit(Op::Unary(operator));
+            }
+            Expr::Variable { id: _, name } => self.get_variable(name)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile(source: &str) -> Result<Option<FunctionProto>, RuntimeError> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        Compiler::compile(&stmts)
+    }
+
+    #[test]
+    fn captures_enclosing_locals() {
+        let script = compile(
+            "fun contador() { var n = 0; fun inc() { n = n + 1; retorna n; } retorna inc; }",
+        )
+        .unwrap()
+        .unwrap();
+        let outer = &script.chunk.functions[0];
+        let inner = &outer.chunk.functions[0];
+        assert_eq!(inner.captures.len(), 1);
+        assert!(inner.captures[0].is_local);
+        assert_eq!(inner.captures[0].index, 1);
+    }
+
+    #[test]
+    fn leaves_nested_declarations_to_the_interpreter() {
+        let script = compile("classe A { }").unwrap().unwrap();
+        assert_eq!(script.chunk.statements.len(), 1);
+        for source in [
+            "fun f() { classe A { } }",
+            "fun f() { fun g() { produz 1; } retorna g(); }",
+            "se (verdadeiro) { contrato C { a(); } }",
+            "{ var g = fun () { produz 1; }; }",
+        ] {
+            assert!(compile(source).unwrap().is_none(), "{source}");
+        }
+    }
+}
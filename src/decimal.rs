@@ -0,0 +1,324 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Most digits kept after the point when a result can't be exact (divisions like 1/3)
+const MAX_SCALE: u32 = 20;
+
+/// How `arredondar` resolves a digit that sits exactly half way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// 2.345 -> 2.34, 2.355 -> 2.36 (bancário, o padrão)
+    HalfEven,
+    /// 2.345 -> 2.35, -2.345 -> -2.35
+    HalfUp,
+}
+
+impl Rounding {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "meio_par" => Ok(Rounding::HalfEven),
+            "meio_acima" => Ok(Rounding::HalfUp),
+            other => Err(format!(
+                "Modo de arredondamento desconhecido '{other}', use 'meio_par' ou 'meio_acima'"
+            )),
+        }
+    }
+}
+
+/// Exact base-10 number: `mantissa / 10^scale`
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+fn overflow() -> String {
+    "Estouro na aritmética decimal".to_string()
+}
+
+fn power_of_ten(exponent: u32) -> Result<i128, String> {
+    10i128.checked_pow(exponent).ok_or_else(overflow)
+}
+
+impl Decimal {
+    /// Reads texts like "10.50", "-3" or "0.001"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Não foi possível converter '{text}' em decimal");
+        let trimmed = text.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits, ""),
+        };
+        if whole.is_empty() && fraction.is_empty()
+            || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let mut mantissa: i128 = 0;
+        for c in whole.chars().chain(fraction.chars()) {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(c as i128 - '0' as i128))
+                .ok_or_else(overflow)?;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+
+        Ok(Decimal {
+            mantissa,
+            scale: fraction.len() as u32,
+        })
+    }
+
+    /// The decimal written the way the number is shown, so 0.1 becomes exactly 0.1
+    pub fn from_f64(x: f64) -> Result<Self, String> {
+        if !x.is_finite() {
+            return Err(format!("Não foi possível converter {x} em decimal"));
+        }
+        Decimal::parse(&x.to_string())
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    pub fn neg(self) -> Self {
+        Decimal {
+            mantissa: -self.mantissa,
+            scale: self.scale,
+        }
+    }
+
+    fn rescale(self, scale: u32) -> Result<Self, String> {
+        let factor = power_of_ten(scale - self.scale)?;
+        Ok(Decimal {
+            mantissa: self.mantissa.checked_mul(factor).ok_or_else(overflow)?,
+            scale,
+        })
+    }
+
+    fn aligned(self, other: Self) -> Result<(i128, i128, u32), String> {
+        let scale = self.scale.max(other.scale);
+        Ok((
+            self.rescale(scale)?.mantissa,
+            other.rescale(scale)?.mantissa,
+            scale,
+        ))
+    }
+
+    pub fn add(self, other: Self) -> Result<Self, String> {
+        let (x, y, scale) = self.aligned(other)?;
+        Ok(Decimal {
+            mantissa: x.checked_add(y).ok_or_else(overflow)?,
+            scale,
+        })
+    }
+
+    pub fn sub(self, other: Self) -> Result<Self, String> {
+        self.add(other.neg())
+    }
+
+    pub fn mul(self, other: Self) -> Result<Self, String> {
+        let product = Decimal {
+            mantissa: self
+                .mantissa
+                .checked_mul(other.mantissa)
+                .ok_or_else(overflow)?,
+            scale: self.scale + other.scale,
+        };
+        if product.scale > MAX_SCALE {
+            product.round(MAX_SCALE, Rounding::HalfEven)
+        } else {
+            Ok(product)
+        }
+    }
+
+    pub fn div(self, other: Self) -> Result<Self, String> {
+        if other.is_zero() {
+            return Err("Divisão por zero".to_string());
+        }
+
+        // Long division of the mantissas, one digit past MAX_SCALE, then rounded
+        let (x, y, _) = self.aligned(other)?;
+        let negative = (x < 0) != (y < 0);
+        let (x, y) = (x.abs(), y.abs());
+        let mut mantissa = x / y;
+        let mut remainder = x % y;
+        for _ in 0..=MAX_SCALE {
+            remainder = remainder.checked_mul(10).ok_or_else(overflow)?;
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(remainder / y))
+                .ok_or_else(overflow)?;
+            remainder %= y;
+        }
+        // A non-zero remainder means the last digit is a bit above what it shows
+        if remainder != 0 && mantissa % 10 == 5 {
+            mantissa += 1;
+        }
+        let quotient = Decimal {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: MAX_SCALE + 1,
+        };
+
+        Ok(quotient.round(MAX_SCALE, Rounding::HalfEven)?.normalized())
+    }
+
+    /// Keeps `places` digits after the point
+    pub fn round(self, places: u32, mode: Rounding) -> Result<Self, String> {
+        if self.scale <= places {
+            return self.rescale(places);
+        }
+
+        let divisor = power_of_ten(self.scale - places)?;
+        let quotient = self.mantissa / divisor;
+        let remainder = (self.mantissa % divisor).abs();
+        // Compared with what is left to the next step, as doubling it may overflow
+        let away = match remainder.cmp(&(divisor - remainder)) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => mode == Rounding::HalfUp || quotient % 2 != 0,
+        };
+        let step = if self.mantissa < 0 { -1 } else { 1 };
+
+        Ok(Decimal {
+            mantissa: if away { quotient + step } else { quotient },
+            scale: places,
+        })
+    }
+
+    /// Drops trailing zeros after the point
+    fn normalized(mut self) -> Self {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.aligned(*other) {
+            Ok((x, y, _)) => x.cmp(&y),
+            // Too many digits to align exactly, the magnitudes are far apart anyway
+            Err(_) => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+}
+
+/// Always at least two places after the point, as in money: 10.5 is shown as 10.50
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.normalized();
+        let digits = value.mantissa.unsigned_abs().to_string();
+        let scale = value.scale as usize;
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        let sign = if value.mantissa < 0 { "-" } else { "" };
+
+        write!(f, "{sign}{whole}.{fraction:0<2}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        let sum = d("0.1").add(d("0.2")).unwrap();
+        assert_eq!(sum, d("0.3"));
+        assert_eq!(sum.to_string(), "0.30");
+    }
+
+    #[test]
+    fn displays_two_places() {
+        assert_eq!(d("10.5").to_string(), "10.50");
+        assert_eq!(d("3").to_string(), "3.00");
+        assert_eq!(d("-0.05").to_string(), "-0.05");
+        assert_eq!(d("1.125").to_string(), "1.125");
+    }
+
+    #[test]
+    fn converts_numbers_as_written() {
+        assert_eq!(Decimal::from_f64(0.1).unwrap(), d("0.1"));
+        assert_eq!(Decimal::from_f64(-12.0).unwrap(), d("-12"));
+        assert!(Decimal::from_f64(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn rounds_half_even_and_half_up() {
+        assert_eq!(d("2.345").round(2, Rounding::HalfEven).unwrap(), d("2.34"));
+        assert_eq!(d("2.355").round(2, Rounding::HalfEven).unwrap(), d("2.36"));
+        assert_eq!(d("2.345").round(2, Rounding::HalfUp).unwrap(), d("2.35"));
+        assert_eq!(d("-2.345").round(2, Rounding::HalfUp).unwrap(), d("-2.35"));
+        assert_eq!(d("2.3451").round(2, Rounding::HalfEven).unwrap(), d("2.35"));
+    }
+
+    #[test]
+    fn rounds_the_largest_mantissas() {
+        let nines = d("0.99999999999999999999999999999999999999");
+        assert_eq!(nines.round(0, Rounding::HalfEven).unwrap(), d("1"));
+        let negative = d("-0.99999999999999999999999999999999999999");
+        assert_eq!(negative.round(0, Rounding::HalfUp).unwrap(), d("-1"));
+        assert_eq!(nines.round(37, Rounding::HalfEven).unwrap(), d("1"));
+    }
+
+    #[test]
+    fn divides() {
+        assert_eq!(d("10").div(d("4")).unwrap(), d("2.5"));
+        assert_eq!(
+            d("1").div(d("3")).unwrap().to_string(),
+            "0.33333333333333333333"
+        );
+        assert_eq!(
+            d("2").div(d("3")).unwrap().to_string(),
+            "0.66666666666666666667"
+        );
+        assert!(d("1").div(d("0")).is_err());
+    }
+
+    #[test]
+    fn multiplies_and_compares() {
+        assert_eq!(d("1.10").mul(d("3")).unwrap(), d("3.3"));
+        assert!(d("0.30") < d("0.301"));
+        assert!(d("-1") < d("0"));
+    }
+
+    #[test]
+    fn rejects_invalid_text() {
+        assert!(Decimal::parse("abc").is_err());
+        assert!(Decimal::parse("1.2.3").is_err());
+        assert!(Decimal::parse("").is_err());
+        assert!(Decimal::parse("-").is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::scanner::Token;
+
+/// The step of running a program that found a problem
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Leitura,
+    Lexica,
+    Sintatica,
+    Semantica,
+    Execucao,
+}
+
+impl Phase {
+    /// Stable name, for the machine-readable output
+    pub fn id(&self) -> &'static str {
+        match self {
+            Phase::Leitura => "leitura",
+            Phase::Lexica => "lexica",
+            Phase::Sintatica => "sintatica",
+            Phase::Semantica => "semantica",
+            Phase::Execucao => "execucao",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Phase::Leitura => "erro de leitura",
+            Phase::Lexica => "erro léxico",
+            Phase::Sintatica => "erro sintático",
+            Phase::Semantica => "erro semântico",
+            Phase::Execucao => "erro de execução",
+        }
+    }
+}
+
+/// A stretch of one source line: columns are counted from 1 and `end` is excluded
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn of(token: &Token) -> Self {
+        Self {
+            line: token.line_number,
+            start: token.column,
+            // The end of the file has no text, but the caret still goes after the last character
+            end: token.column + token.lexeme.chars().count().max(1),
+        }
+    }
+}
+
+/// A problem found in a program, with where it is and what to do about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub phase: Phase,
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    // 0 when the line is unknown
+    pub line: usize,
+    pub columns: Option<(usize, usize)>,
+    pub hint: Option<String>,
+    // The offending line of the program, for the underline
+    pub source_line: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(phase: Phase, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            phase,
+            code,
+            message: message.into(),
+            file: None,
+            line: 0,
+            columns: None,
+            hint: None,
+            source_line: None,
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.line = span.line;
+        self.columns = Some((span.start, span.end));
+        self
+    }
+
+    pub fn at_token(self, token: &Token) -> Self {
+        self.at(Span::of(token))
+    }
+
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self.columns = None;
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Keeps the offending line of `source`, for `render`
+    pub fn with_source(mut self, source: &str) -> Self {
+        if self.line > 0 {
+            self.source_line = source.lines().nth(self.line - 1).map(|line| line.to_string());
+        }
+        self
+    }
+
+    /// Human-readable form, underlining the offending text:
+    ///
+    /// erro sintático [S001]: Esperado ';' depois do valor
+    ///  --> linha 1, coluna 12
+    ///   |
+    /// 1 | saida 1 + 2
+    ///   |            ^
+    pub fn render(&self) -> String {
+        // Lines after the first (the call trace of runtime errors) go below the source
+        let (headline, details) = match self.message.split_once('\n') {
+            Some((headline, details)) => (headline, Some(details)),
+            None => (self.message.as_str(), None),
+        };
+        let mut out = format!("{} [{}]: {headline}", self.phase.title(), self.code);
+
+        let mut location = vec![];
+        if let Some(file) = &self.file {
+            location.push(file.clone());
+        }
+        if self.line > 0 {
+            location.push(format!("linha {}", self.line));
+        }
+        if let Some((start, _)) = self.columns {
+            location.push(format!("coluna {start}"));
+        }
+        let number = self.line.to_string();
+        let margin = " ".repeat(number.len());
+        if !location.is_empty() {
+            out.push_str(&format!("\n{margin}--> {}", location.join(", ")));
+        }
+
+        if let Some(source_line) = &self.source_line {
+            out.push_str(&format!("\n{margin} |\n{number} | {source_line}"));
+            if let Some((start, end)) = self.columns {
+                // Text that runs past the line (a multi-line string) is underlined up to its end
+                let width = source_line.chars().count() + 1;
+                let start = start.clamp(1, width);
+                let end = end.clamp(start + 1, width + 1);
+                out.push_str(&format!(
+                    "\n{margin} | {}{}",
+                    " ".repeat(start - 1),
+                    "^".repeat(end - start)
+                ));
+            }
+        }
+
+        if let Some(details) = details {
+            out.push('\n');
+            out.push_str(details);
+        }
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("\n{margin} = dica: {hint}"));
+        }
+        out
+    }
+
+    /// One JSON object in a single line, for editors
+    pub fn to_json(&self) -> String {
+        let text = |value: Option<&str>| match value {
+            Some(value) => json_string(value),
+            None => "null".to_string(),
+        };
+        let number = |value: Option<usize>| match value {
+            Some(value) => value.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"fase\":{},\"codigo\":{},\"mensagem\":{},\"arquivo\":{},\"linha\":{},\"coluna\":{},\"coluna_final\":{},\"dica\":{}}}",
+            json_string(self.phase.id()),
+            json_string(self.code),
+            json_string(&self.message),
+            text(self.file.as_deref()),
+            number(Some(self.line).filter(|line| *line > 0)),
+            number(self.columns.map(|(start, _)| start)),
+            number(self.columns.map(|(_, end)| end)),
+            text(self.hint.as_deref()),
+        )
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A runtime error on its way up from where it was raised. The innermost expression or
+/// statement it passes through that knows its place in the program sets it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub code: &'static str,
+    pub message: String,
+    // 0 while the place is unknown
+    pub line: usize,
+    pub columns: Option<(usize, usize)>,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            code: "E001",
+            message: message.into(),
+            line: 0,
+            columns: None,
+        }
+    }
+
+    /// Places the error at `span`, unless a more precise place inside it already did. Code
+    /// the compiler generated on its own has no place in the program.
+    pub fn raised_at(mut self, span: Span) -> Self {
+        if self.columns.is_none() && span.line > 0 {
+            self.line = span.line;
+            self.columns = Some((span.start, span.end));
+        }
+        self
+    }
+
+    /// The diagnostic of an uncaught runtime error, with the calls that led to it
+    pub fn into_diagnostic(self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new(Phase::Execucao, self.code, self.message);
+        diagnostic.line = self.line;
+        diagnostic.columns = self.columns;
+        diagnostic.message = crate::callstack::with_trace(diagnostic.message);
+        // A program stopped by its limits didn't do anything wrong where it stopped
+        if self.code == crate::budget::CODE {
+            diagnostic = diagnostic.with_hint("Os limites vêm das opções --passos, --tempo e --saida");
+        }
+        diagnostic
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Diagnostic {
+        Diagnostic::new(Phase::Sintatica, "S001", "Esperado ';' depois do valor")
+            .at(Span {
+                line: 2,
+                start: 12,
+                end: 13,
+            })
+            .with_hint("Termine o comando com ';'")
+            .with_source("var a = 1;\nsaida 1 + 2\n")
+    }
+
+    #[test]
+    fn underlines_the_offending_text() {
+        assert_eq!(
+            example().render(),
+            "erro sintático [S001]: Esperado ';' depois do valor\n \
+             --> linha 2, coluna 12\n  \
+             |\n\
+             2 | saida 1 + 2\n  \
+             |            ^\n  \
+             = dica: Termine o comando com ';'"
+        );
+    }
+
+    #[test]
+    fn serializes_to_one_json_line() {
+        assert_eq!(
+            example().in_file("a\"b.fe").to_json(),
+            r#"{"fase":"sintatica","codigo":"S001","mensagem":"Esperado ';' depois do valor","arquivo":"a\"b.fe","linha":2,"coluna":12,"coluna_final":13,"dica":"Termine o comando com ';'"}"#
+        );
+    }
+
+    #[test]
+    fn keeps_the_innermost_place_of_a_runtime_error() {
+        let inner = Span {
+            line: 3,
+            start: 7,
+            end: 9,
+        };
+        let outer = Span {
+            line: 3,
+            start: 1,
+            end: 2,
+        };
+        let error = RuntimeError::new("índice fora dos limites")
+            .raised_at(inner)
+            .raised_at(outer);
+        let diagnostic = error.into_diagnostic();
+        assert_eq!(diagnostic.line, 3);
+        assert_eq!(diagnostic.columns, Some((7, 9)));
+        assert_eq!(diagnostic.message, "índice fora dos limites");
+    }
+}
@@ -1,16 +1,25 @@
-use crate::expr::{CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::decimal::Decimal;
+use crate::expr::{Arity, CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::heap::{self, Fields, Table};
+use crate::math::{math_module, round_value};
+use crate::resolver::LocalSlot;
+use crate::strings::string_module;
 use std::cell::RefCell;
+use std::io::Write;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct Environment {
-    pub values: Rc<RefCell<HashMap<String, LiteralValue>>>,
-    locals: Rc<RefCell<HashMap<usize, usize>>>,
-    pub enclosing: Option<Box<Environment>>,
+    // Variables declared outside any block or function, shared by every scope
+    globals: Rc<Table>,
+    // Variables of a local scope, at the slots given by the resolver
+    slots: Rc<Fields>,
+    locals: Rc<RefCell<HashMap<usize, LocalSlot>>>,
+    pub enclosing: Option<Rc<Environment>>,
 }
 
-fn clock_impl(_args: &Vec<LiteralValue>) -> LiteralValue {
+fn clock_impl(_args: &Vec<LiteralValue>) -> Result<LiteralValue, String> {
     
     let now = std::time::SystemTime::now();
     let duration = now.duration_since(std::time::SystemTime::UNIX_EPOCH).expect("Erro ao obter horário");
@@ -31,32 +40,112 @@ fn clock_impl(_args: &Vec<LiteralValue>) -> LiteralValue {
         day, month + 1, year, hours, minutes, seconds
     );
 
-     LiteralValue::StringValue(formatted_time)
+     Ok(LiteralValue::StringValue(formatted_time))
 }
 
-fn get_globals() -> Rc<RefCell<HashMap<String, LiteralValue>>> {
+/// decimal("10.50") or decimal(10.5)
+fn decimal_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match args {
+        [LiteralValue::StringValue(text)] => Ok(LiteralValue::DecimalValue(Decimal::parse(text)?)),
+        [LiteralValue::Number(x)] => Ok(LiteralValue::DecimalValue(Decimal::from_f64(*x)?)),
+        [LiteralValue::DecimalValue(x)] => Ok(LiteralValue::DecimalValue(*x)),
+        [other] => Err(format!("Não é possível converter um {} em decimal", other.to_type())),
+        _ => Err(format!("A função decimal esperava 1 argumento mas encontrou {}", args.len())),
+    }
+}
+
+/// arredondar(valor, casas) or arredondar(valor, casas, "meio_acima"), the same as mat.arredondar
+fn round_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    round_value("arredondar", args)
+}
+
+/// The digits of a whole number in `base`, padded with zeros to `binario(n, largura)`
+fn digits_impl(name: &str, args: &[LiteralValue], base: u32) -> Result<LiteralValue, String> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(format!("A função {name} esperava 1 ou 2 argumentos mas encontrou {}", args.len()));
+    }
+    let n = match &args[0] {
+        LiteralValue::Number(x) if x.fract() == 0.0 && x.abs() <= i64::MAX as f64 => *x as i64,
+        other => return Err(format!("A função {name} exige um número inteiro, não {}", other.to_string())),
+    };
+    let width = match args.get(1) {
+        None => 0,
+        Some(LiteralValue::Number(x)) if *x >= 0.0 && x.fract() == 0.0 => *x as usize,
+        Some(other) => return Err(format!("A largura deve ser um inteiro positivo, não {}", other.to_string())),
+    };
+    // The widest padding the formatter accepts
+    if width > u16::MAX as usize {
+        return Err(format!("A largura deve ser no máximo {}, não {width}", u16::MAX));
+    }
+
+    let digits = match base {
+        2 => format!("{:0width$b}", n.unsigned_abs()),
+        _ => format!("{:0width$X}", n.unsigned_abs()),
+    };
+    let sign = if n < 0 { "-" } else { "" };
+    Ok(LiteralValue::StringValue(format!("{sign}{digits}")))
+}
+
+fn binary_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    digits_impl("binario", args, 2)
+}
+
+fn hex_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    digits_impl("hex", args, 16)
+}
+
+/// sair(codigo) ends the program with that exit status; sair() is sair(0)
+fn exit_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let code = match args {
+        [] => 0,
+        [LiteralValue::Number(x)] if x.fract() == 0.0 && (0.0..=255.0).contains(x) => *x as i32,
+        [other] => return Err(format!("O código de saída deve ser um inteiro entre 0 e 255, não {}", other.to_string())),
+        _ => return Err(format!("A função sair esperava 1 argumento mas encontrou {}", args.len())),
+    };
+    let _ = std::io::stdout().flush();
+    std::process::exit(code);
+}
+
+fn native(env: &mut HashMap<String, LiteralValue>, name: &str, arity: Arity, fun: fn(&[LiteralValue]) -> Result<LiteralValue, String>) {
+    let fun_impl = NativeFunctionImpl {
+        name: name.to_string(),
+        arity,
+        fun: Rc::new(move |args: &Vec<LiteralValue>| Ok(fun(args)?)),
+    };
+    env.insert(name.to_string(), LiteralValue::Callable(CallableImpl::NativeFunction(fun_impl)));
+}
+
+fn get_globals() -> Rc<Table> {
     let mut env = HashMap::new();
     let fun_impl = NativeFunctionImpl {
         name: "agora".to_string(),
-        arity: 0,
-        fun: Rc::new(clock_impl),
+        arity: Arity::Exactly(0),
+        fun: Rc::new(|args: &Vec<LiteralValue>| Ok(clock_impl(args)?)),
     };
     let callable_impl = CallableImpl::NativeFunction(fun_impl);
     env.insert("agora".to_string(), LiteralValue::Callable(callable_impl));
+    native(&mut env, "decimal", Arity::Exactly(1), decimal_impl);
+    native(&mut env, "arredondar", Arity::Between(2, 3), round_impl);
+    native(&mut env, "binario", Arity::Between(1, 2), binary_impl);
+    native(&mut env, "hex", Arity::Between(1, 2), hex_impl);
+    native(&mut env, "sair", Arity::Between(0, 1), exit_impl);
+    env.insert("texto".to_string(), string_module());
+    env.insert("mat".to_string(), math_module());
 
-    Rc::new(RefCell::new(env))
+    heap::table(env)
 }
 
 impl Environment {
-    pub fn new(locals: HashMap<usize, usize>) -> Self {
+    pub fn new(locals: HashMap<usize, LocalSlot>) -> Self {
         Self {
-            values: get_globals(),
+            globals: get_globals(),
+            slots: heap::fields(vec![]),
             locals: Rc::new(RefCell::new(locals)),
             enclosing: None,
         }
     }
 
-    pub fn resolve(&self, locals: HashMap<usize, usize>) {
+    pub fn resolve(&self, locals: HashMap<usize, LocalSlot>) {
         // self.locals = locals --! Bad because it wont update enclosing
         for (key, val) in locals.iter() {
             self.locals.borrow_mut().insert(*key, *val);
@@ -65,95 +154,145 @@ impl Environment {
 
     pub fn enclose(&self) -> Environment {
         Self {
-            values: Rc::new(RefCell::new(HashMap::new())),
+            globals: self.globals.clone(),
+            slots: heap::fields(vec![]),
             locals: self.locals.clone(),
-            enclosing: Some(Box::new(self.clone())),
+            enclosing: Some(heap::scope(self.clone())),
         }
     }
 
+    fn is_global(&self) -> bool {
+        self.enclosing.is_none()
+    }
+
+    /// Declares a variable in this scope. Declarations run in the order the resolver saw
+    /// them, so each one lands on the next slot, the one it was given
     pub fn define(&self, name: String, value: LiteralValue) {
-        self.values.borrow_mut().insert(name, value);
+        if self.is_global() {
+            self.globals.borrow_mut().insert(name, value);
+            return;
+        }
+
+        self.slots.borrow_mut().push((name, value));
+    }
+
+    /// Gives the variable declared last in this scope its value, for a class, which is
+    /// declared before its methods are made
+    pub fn redefine(&self, name: &str, value: LiteralValue) {
+        if self.is_global() {
+            self.globals.borrow_mut().insert(name.to_string(), value);
+            return;
+        }
+
+        let index = self.slots.borrow().len() - 1;
+        self.set_at(name, index, value);
+    }
+
+    /// Gives a declared variable of this scope a new value, such as the variable of
+    /// `para ... em` on each iteration
+    pub fn set_at(&self, name: &str, index: usize, value: LiteralValue) {
+        let mut slots = self.slots.borrow_mut();
+        let slot = &mut slots[index];
+        debug_assert_eq!(slot.0, name, "a variável não está no espaço dado pelo resolvedor");
+        slot.1 = value;
     }
 
     pub fn get(&self, name: &str, expr_id: usize) -> Option<LiteralValue> {
-        let distance = self.locals.borrow().get(&expr_id).cloned();
-        self.get_internal(name, distance)
+        let slot = self.locals.borrow().get(&expr_id).copied();
+        match slot {
+            None => self.get_global(name),
+            Some(slot) => self.ancestor(slot.distance)?.get_at(name, slot.index),
+        }
     }
 
+    pub fn get_global(&self, name: &str) -> Option<LiteralValue> {
+        self.globals.borrow().get(name).cloned()
+    }
+
+    /// The `_objeto` of a method, found from its use of `super`: it is declared in the scope
+    /// right inside the one holding `super`
     pub fn get_this_instance(&self, super_id: usize) -> Option<LiteralValue> {
-        let distance = self
-            .locals
-            .borrow()
-            .get(&super_id)
-            .cloned()
-            .expect("Não foi encontrado o _objeto mesmo com 'super' estando definido");
-        self.get_internal("this", Some(distance - 1))
+        let slot = self.locals.borrow().get(&super_id).copied()?;
+        self.ancestor(slot.distance.checked_sub(1)?)?
+            .get_at("_objeto", 0)
     }
 
     pub fn get_distance(&self, expr_id: usize) -> Option<usize> {
-        self.locals.borrow().get(&expr_id).cloned()
+        self.locals.borrow().get(&expr_id).map(|slot| slot.distance)
     }
 
-    fn get_internal(&self, name: &str, distance: Option<usize>) -> Option<LiteralValue> {
-        if let None = distance {
-            match &self.enclosing {
-                None => self.values.borrow().get(name).cloned(),
-                Some(env) => env.get_internal(name, distance),
-            }
-        } else {
-            let distance = distance.unwrap();
-            if distance == 0 {
-                self.values.borrow().get(name).cloned()
-            } else {
-                match &self.enclosing {
-                    None => panic!("Tried to resolve a variable that was defined deeper than the current environment depth"),
-                    Some(env) => {
-                        assert!(distance > 0);
-                        env.get_internal(name, Some(distance - 1))
-                    }
-                }
-            }
+    /// The scope `distance` levels up from this one
+    fn ancestor(&self, distance: usize) -> Option<&Environment> {
+        let mut env = self;
+        for _ in 0..distance {
+            env = env.enclosing.as_deref()?;
+        }
+        Some(env)
+    }
+
+    /// The variable at `index` in this scope
+    fn get_at(&self, name: &str, index: usize) -> Option<LiteralValue> {
+        if self.is_global() {
+            return self.get_global(name);
         }
+
+        let slots = self.slots.borrow();
+        let (slot_name, value) = slots.get(index)?;
+        debug_assert_eq!(slot_name, name, "a variável não está no espaço dado pelo resolvedor");
+        Some(value.clone())
     }
 
     pub fn assign_global(&self, name: &str, value: LiteralValue) -> bool {
-        self.assign_internal(name, value, None)
+        self.globals
+            .borrow_mut()
+            .insert(name.to_string(), value)
+            .is_some()
     }
 
     pub fn assign(&self, name: &str, value: LiteralValue, expr_id: usize) -> bool {
         // ! Important that this ID matches with the resolver
-        let distance = self.locals.borrow().get(&expr_id).cloned();
-        self.assign_internal(name, value, distance)
-    }
-
-    fn assign_internal(&self, name: &str, value: LiteralValue, distance: Option<usize>) -> bool {
-        if let None = distance {
-            match &self.enclosing {
-                Some(env) => env.assign_internal(name, value, distance),
-                None => match self.values.borrow_mut().insert(name.to_string(), value) {
-                    Some(_) => true,
-                    None => false,
-                },
-            }
-        } else {
-            let distance = distance.unwrap();
-            if distance == 0 {
-                self.values.borrow_mut().insert(name.to_string(), value);
-                true
-            } else {
-                match &self.enclosing {
-                    None => panic!("Tried to define a variable in a too deep level"),
-                    Some(env) => env.assign_internal(name, value, Some(distance - 1)),
-                };
-                true
-            }
+        let slot = self.locals.borrow().get(&expr_id).copied();
+        let Some(slot) = slot else {
+            return self.assign_global(name, value);
+        };
+        let Some(env) = self.ancestor(slot.distance) else {
+            return false;
+        };
+        if env.is_global() {
+            return env.assign_global(name, value);
+        }
+
+        let mut slots = env.slots.borrow_mut();
+        let Some((slot_name, slot_value)) = slots.get_mut(slot.index) else {
+            return false;
+        };
+        debug_assert_eq!(slot_name, name, "a variável não está no espaço dado pelo resolvedor");
+        *slot_value = value;
+        true
+    }
+
+    /// The shared values this scope holds, for the cycle collector
+    pub fn trace(&self, edges: &mut Vec<usize>) {
+        edges.push(heap::address(&self.globals));
+        edges.push(heap::address(&self.slots));
+        if let Some(enclosing) = &self.enclosing {
+            edges.push(heap::address(enclosing));
         }
     }
 
     #[allow(dead_code)]
     pub fn dump(&self, indent: usize) -> String {
         let mut result = String::new();
-        for (key, val) in self.values.borrow().iter() {
+        let variables: Vec<(String, LiteralValue)> = if self.is_global() {
+            self.globals
+                .borrow()
+                .iter()
+                .map(|(key, val)| (key.clone(), val.clone()))
+                .collect()
+        } else {
+            self.slots.borrow().clone()
+        };
+        for (key, val) in variables.iter() {
             for _ in 0..indent {
                 result.push_str(" ");
             }
@@ -174,4 +313,46 @@ mod tests {
     fn try_init() {
         let _environment = Environment::new(HashMap::new());
     }
+
+    #[test]
+    fn pads_digits_up_to_the_widest_width() {
+        let args = |width: f64| [LiteralValue::Number(5.0), LiteralValue::Number(width)];
+        assert_eq!(binary_impl(&args(8.0)), Ok(LiteralValue::StringValue("00000101".to_string())));
+        assert!(hex_impl(&args(65535.0)).is_ok());
+        assert!(binary_impl(&args(100000.0)).is_err());
+    }
+
+    #[test]
+    fn reads_and_assigns_by_slot() {
+        // Expression 1 reads `b` one scope up, expression 2 assigns `x` in its own scope
+        let mut locals = HashMap::new();
+        locals.insert(1, LocalSlot { distance: 1, index: 1 });
+        locals.insert(2, LocalSlot { distance: 0, index: 0 });
+        let global = Environment::new(locals);
+        let outer = global.enclose();
+        outer.define("a".to_string(), LiteralValue::Number(1.0));
+        outer.define("b".to_string(), LiteralValue::Number(2.0));
+        let inner = outer.enclose();
+        inner.define("x".to_string(), LiteralValue::Nil);
+
+        assert_eq!(inner.get("b", 1), Some(LiteralValue::Number(2.0)));
+        assert!(inner.assign("x", LiteralValue::True, 2));
+        assert_eq!(inner.get("x", 2), Some(LiteralValue::True));
+        inner.set_at("x", 0, LiteralValue::False);
+        assert_eq!(inner.get("x", 2), Some(LiteralValue::False));
+        // Not resolved: a global
+        assert_eq!(inner.get("a", 3), None);
+        assert!(inner.get("mat", 3).is_some());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "espaço dado pelo resolvedor")]
+    fn checks_the_name_at_the_slot() {
+        let mut locals = HashMap::new();
+        locals.insert(1, LocalSlot { distance: 0, index: 0 });
+        let scope = Environment::new(locals).enclose();
+        scope.define("a".to_string(), LiteralValue::Nil);
+        scope.get("b", 1);
+    }
 }
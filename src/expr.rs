@@ -1,7 +1,13 @@
+use crate::callstack::{self, call_name};
+use crate::decimal::Decimal;
+use crate::diagnostic::{RuntimeError, Span};
 use crate::environment::Environment;
-use crate::interpreter::Interpreter;
+use crate::generator::{contains_yield, GeneratorState};
+use crate::heap;
+use crate::interpreter::{Flow, Interpreter};
 use crate::scanner;
 use crate::scanner::{Token, TokenType};
+use crate::strings::{bound_string_method, char_at};
 use std::cell::RefCell;
 use std::cmp::{Eq, PartialEq};
 use std::collections::HashMap;
@@ -13,6 +19,8 @@ use std::rc::Rc;
 pub enum CallableImpl {
     LoxFunction(LoxFunctionImpl),
     NativeFunction(NativeFunctionImpl),
+    // Function compiled to bytecode, run by the VM (`fe --vm`)
+    Compiled(Rc<crate::vm::Closure>),
 }
 use CallableImpl::*;
 
@@ -21,20 +29,67 @@ pub struct LoxFunctionImpl {
     pub name: String,
     pub arity: usize,
     pub parent_env: Environment,
-    pub params: Vec<Token>,
-    pub body: Vec<Box<Stmt>>,
+    // Shared by every copy of the function value, so reading a function doesn't copy its code
+    pub params: Rc<Vec<Token>>,
+    pub body: Rc<Vec<Stmt>>,
+    // Calling a function that contains `produz` returns a generator instead of running it
+    pub is_generator: bool,
+    // Class of the instance a method is bound to, for stack traces
+    pub class_name: Option<String>,
 }
 
+pub type NativeFn = Rc<dyn Fn(&Vec<LiteralValue>) -> Result<LiteralValue, RuntimeError>>;
+
 #[derive(Clone)]
 pub struct NativeFunctionImpl {
     pub name: String,
-    pub arity: usize,
-    pub fun: Rc<dyn Fn(&Vec<LiteralValue>) -> LiteralValue>,
+    pub arity: Arity,
+    pub fun: NativeFn,
+}
+
+/// How many arguments a built-in function accepts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    // Both bounds included, as in `binario(n)` and `binario(n, largura)`
+    Between(usize, usize),
+    // `mat.min` and `mat.max` take any number of arguments
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => count == n,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+            Arity::AtLeast(min) => count >= min,
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Arity::Exactly(n) => n.to_string(),
+            Arity::Between(min, max) if max == min + 1 => format!("{min} ou {max}"),
+            Arity::Between(min, max) => format!("de {min} a {max}"),
+            Arity::AtLeast(min) => format!("ao menos {min}"),
+        }
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exactly(n) => write!(f, "{n}"),
+            Arity::Between(min, max) => write!(f, "{min}..{max}"),
+            Arity::AtLeast(min) => write!(f, "{min}.."),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum LiteralValue {
     Number(f64),
+    DecimalValue(Decimal),
     StringValue(String),
     True,
     False,
@@ -44,11 +99,49 @@ pub enum LiteralValue {
         name: String,
         methods: HashMap<String, LoxFunctionImpl>,
         superclass: Option<Box<LiteralValue>>,
-        //methods: Vec<(String, LiteralValue)>, // TODO Could also add static fields?
+        static_methods: HashMap<String, LoxFunctionImpl>,
+        // Shared between every copy of the class value, so `Classe.campo = x` is seen by all
+        statics: Rc<RefCell<HashMap<String, LiteralValue>>>,
+        getters: HashMap<String, LoxFunctionImpl>,
+        setters: HashMap<String, LoxFunctionImpl>,
+        // Declared with `campo`, and given their values by `field_values` on each new object
+        fields: Vec<String>,
+        field_values: Option<Box<LoxFunctionImpl>>,
+        // Only fields declared by the class or its superclasses can be assigned
+        strict: bool,
     },
     LoxInstance {
         class: Box<LiteralValue>,
-        fields: Rc<RefCell<Vec<(String, LiteralValue)>>>,
+        fields: Rc<RefCell<HashMap<String, LiteralValue>>>,
+    },
+    Array {
+        name: String,
+        bounds: Vec<(i64, i64)>,
+        elements: Rc<RefCell<Vec<LiteralValue>>>,
+    },
+    Contract {
+        name: String,
+        methods: Vec<(String, usize)>,
+    },
+    Mixin {
+        name: String,
+        methods: HashMap<String, LoxFunctionImpl>,
+    },
+    Tuple(Vec<LiteralValue>),
+    Range {
+        start: f64,
+        end: f64,
+        step: f64,
+        inclusive: bool,
+    },
+    Generator {
+        name: String,
+        state: Rc<RefCell<GeneratorState>>,
+    },
+    // Built-in group of functions such as `texto`, reached with `texto.maiusculo`
+    Namespace {
+        name: String,
+        members: Rc<HashMap<String, LiteralValue>>,
     },
 }
 use LiteralValue::*;
@@ -63,6 +156,7 @@ impl PartialEq for LiteralValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Number(x), Number(y)) => x == y,
+            (DecimalValue(x), DecimalValue(y)) => x == y,
             (
                 Callable(CallableImpl::LoxFunction(LoxFunctionImpl { name, arity, .. })),
                 Callable(CallableImpl::LoxFunction(LoxFunctionImpl {
@@ -79,7 +173,29 @@ impl PartialEq for LiteralValue {
                     ..
                 })),
             ) => name == name2 && arity == arity2,
+            (Callable(CallableImpl::Compiled(x)), Callable(CallableImpl::Compiled(y))) => Rc::ptr_eq(x, y),
             (StringValue(x), StringValue(y)) => x == y,
+            (Tuple(x), Tuple(y)) => x == y,
+            (
+                Range {
+                    start,
+                    end,
+                    step,
+                    inclusive,
+                },
+                Range {
+                    start: start2,
+                    end: end2,
+                    step: step2,
+                    inclusive: inclusive2,
+                },
+            ) => start == start2 && end == end2 && step == step2 && inclusive == inclusive2,
+            (Array { elements: x, .. }, Array { elements: y, .. }) => Rc::ptr_eq(x, y),
+            (Generator { state: x, .. }, Generator { state: y, .. }) => Rc::ptr_eq(x, y),
+            (Namespace { members: x, .. }, Namespace { members: y, .. }) => Rc::ptr_eq(x, y),
+            // Identity: the same object, or the same class declaration
+            (LoxInstance { fields: x, .. }, LoxInstance { fields: y, .. }) => Rc::ptr_eq(x, y),
+            (LoxClass { statics: x, .. }, LoxClass { statics: y, .. }) => Rc::ptr_eq(x, y),
             (True, True) => true,
             (False, False) => true,
             (Nil, Nil) => true,
@@ -95,6 +211,16 @@ fn unwrap_as_f64(literal: Option<scanner::LiteralValue>) -> f64 {
     }
 }
 
+fn unwrap_as_decimal(literal: Option<scanner::LiteralValue>) -> Decimal {
+    match literal {
+        Some(scanner::LiteralValue::StringValue(s)) => match Decimal::parse(&s) {
+            Ok(x) => x,
+            Err(msg) => panic!("{msg}"),
+        },
+        _ => panic!("Não foi possível converter em decimal"),
+    }
+}
+
 fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> String {
     match literal {
         Some(scanner::LiteralValue::StringValue(s)) => s.clone(),
@@ -102,25 +228,24 @@ fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> String {
     }
 }
 
-macro_rules! class_name {
-    ($class:expr) => {{
-        if let LiteralValue::LoxClass {
-            name,
-            methods: _,
-            superclass: _,
-        } = &**$class
-        {
-            name
-        } else {
-            panic!("Não era esperado este erro [expr.rs 115]")
-        }
-    }};
+/// Name of the class of an instance
+/// An error raised at `token`
+fn error_at(token: &Token, message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(message).raised_at(Span::of(token))
+}
+
+fn class_name(class: &LiteralValue) -> &str {
+    match class {
+        LiteralValue::LoxClass { name, .. } => name,
+        other => other.to_type(),
+    }
 }
 
 impl LiteralValue {
     pub fn to_string(&self) -> String {
         match self {
             LiteralValue::Number(x) => x.to_string(),
+            LiteralValue::DecimalValue(x) => x.to_string(),
             LiteralValue::StringValue(x) => format!("\'{}\'", x),
             LiteralValue::True => "verdadeiro".to_string(),
             LiteralValue::False => "falso".to_string(),
@@ -135,37 +260,77 @@ impl LiteralValue {
                 arity,
                 ..
             })) => format!("{name}/{arity}"),
-            LiteralValue::LoxClass {
-                name,
-                methods: _,
-                superclass: _,
-            } => format!("Classe '{name}'"),
+            LiteralValue::Callable(CallableImpl::Compiled(closure)) => {
+                format!("{}/{}", closure.proto.name, closure.proto.arity)
+            }
+            LiteralValue::LoxClass { name, .. } => format!("Classe '{name}'"),
             LiteralValue::LoxInstance { class, fields: _ } => {
-                format!("Instância '{}'", class_name!(class))
+                format!("Instância '{}'", class_name(class))
             }
+            LiteralValue::Array {
+                name: _,
+                bounds,
+                elements,
+            } => array_to_string(bounds, &elements.borrow()),
+            LiteralValue::Contract { name, .. } => format!("Contrato '{name}'"),
+            LiteralValue::Mixin { name, .. } => format!("Mixin '{name}'"),
+            LiteralValue::Generator { name, .. } => format!("Gerador '{name}'"),
+            LiteralValue::Namespace { name, .. } => format!("Módulo '{name}'"),
+            LiteralValue::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let operator = if *inclusive { "..=" } else { ".." };
+                if *step == 1.0 {
+                    format!("{start}{operator}{end}")
+                } else {
+                    format!("{start}{operator}{end} passo {step}")
+                }
+            }
+            LiteralValue::Tuple(elements) => format!(
+                "({})",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
         }
     }
 
     pub fn to_type(&self) -> &str {
         match self {
             LiteralValue::Number(_) => "Número",
+            LiteralValue::DecimalValue(_) => "Decimal",
             LiteralValue::StringValue(_) => "Texto",
             LiteralValue::True => "Valor Lógico",
             LiteralValue::False => "Valor Lógico",
             LiteralValue::Nil => "vazio",
             LiteralValue::Callable(_) => "Função",
-            LiteralValue::LoxClass {
-                name: _,
-                methods: _,
-                superclass: _,
-            } => "Classe",
-            LiteralValue::LoxInstance { class, fields: _ } => &class_name!(class),
+            LiteralValue::LoxClass { .. } => "Classe",
+            LiteralValue::LoxInstance { class, fields: _ } => class_name(class),
+            LiteralValue::Array { bounds, .. } => {
+                if bounds.len() == 1 {
+                    "Vetor"
+                } else {
+                    "Matriz"
+                }
+            }
+            LiteralValue::Contract { .. } => "Contrato",
+            LiteralValue::Mixin { .. } => "Mixin",
+            LiteralValue::Tuple(_) => "Tupla",
+            LiteralValue::Range { .. } => "Intervalo",
+            LiteralValue::Generator { .. } => "Gerador",
+            LiteralValue::Namespace { .. } => "Módulo",
         }
     }
 
     pub fn from_token(token: Token) -> Self {
         match token.token_type {
             TokenType::Number => Self::Number(unwrap_as_f64(token.literal)),
+            TokenType::DecimalLit => Self::DecimalValue(unwrap_as_decimal(token.literal)),
             TokenType::StringLit => Self::StringValue(unwrap_as_string(token.literal)),
             TokenType::False => Self::False,
             TokenType::True => Self::True,
@@ -182,33 +347,12 @@ impl LiteralValue {
         }
     }
 
-    pub fn is_falsy(&self) -> LiteralValue {
-        match self {
-            Number(x) => {
-                if *x == 0.0 as f64 {
-                    True
-                } else {
-                    False
-                }
-            }
-            StringValue(s) => {
-                if s.len() == 0 {
-                    True
-                } else {
-                    False
-                }
-            }
-            True => False,
-            False => True,
-            Nil => True,
-            Callable(_) => panic!("Função não possui retorno booleano"),
-            LoxClass { .. } => panic!("Classe não é um tipo lógico"),
-            _ => panic!("Valor lógico inválido"),
-        }
+    pub fn is_falsy(&self) -> Result<LiteralValue, String> {
+        Ok(LiteralValue::from_bool(self.is_truthy()? == False))
     }
 
-    pub fn is_truthy(&self) -> LiteralValue {
-        match self {
+    pub fn is_truthy(&self) -> Result<LiteralValue, String> {
+        let truth = match self {
             Number(x) => {
                 if *x == 0.0 as f64 {
                     False
@@ -223,13 +367,24 @@ impl LiteralValue {
                     True
                 }
             }
+            DecimalValue(x) => LiteralValue::from_bool(!x.is_zero()),
+            Array { .. } => True,
+            Tuple(elements) => LiteralValue::from_bool(!elements.is_empty()),
+            Range { .. } => True,
+            Generator { .. } => True,
+            Namespace { .. } => True,
             True => True,
             False => False,
             Nil => False,
-            Callable(_) => panic!("Função não possui retorno booleano [verdadeiro]"),
-            LoxClass { .. } => panic!("Classe não é um tipo lógico [verdadeiro]"),
-            _ => panic!("Valor lógico inválido [verdadeiro]"),
-        }
+            Callable(_) | LoxClass { .. } | LoxInstance { .. } | Contract { .. } | Mixin { .. } => {
+                return Err(format!(
+                    "{} não é um valor lógico: {}",
+                    self.to_type(),
+                    self.to_string()
+                ))
+            }
+        };
+        Ok(truth)
     }
 }
 
@@ -241,13 +396,24 @@ pub enum Expr {
         id: usize,
         paren: Token,
         arguments: Vec<Token>,
-        body: Vec<Box<Stmt>>,
+        body: Vec<Stmt>,
+    },
+    Array {
+        id: usize,
+        name: Token,
+        bounds: Vec<(Expr, Expr)>,
+        element_type: Token,
     },
     Assign {
         id: usize,
         name: Token,
         value: Box<Expr>,
     },
+    AssignTuple {
+        id: usize,
+        targets: Vec<Expr>,
+        value: Box<Expr>,
+    },
     Binary {
         id: usize,
         left: Box<Expr>,
@@ -271,6 +437,12 @@ pub enum Expr {
         id: usize,
         expression: Box<Expr>,
     },
+    Index {
+        id: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        indices: Vec<Expr>,
+    },
     Literal {
         id: usize,
         value: LiteralValue,
@@ -287,10 +459,29 @@ pub enum Expr {
         name: Token,
         value: Box<Expr>,
     },
+    SetIndex {
+        id: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        indices: Vec<Expr>,
+        value: Box<Expr>,
+    },
     This {
         id: usize,
         keyword: Token,
     },
+    Tuple {
+        id: usize,
+        elements: Vec<Expr>,
+    },
+    Range {
+        id: usize,
+        start: Box<Expr>,
+        operator: Token,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        inclusive: bool,
+    },
     Super {
         id: usize,
         keyword: Token,
@@ -338,11 +529,22 @@ impl Expr {
                 arguments: _,
                 body: _,
             } => *id,
+            Expr::Array {
+                id,
+                name: _,
+                bounds: _,
+                element_type: _,
+            } => *id,
             Expr::Assign {
                 id,
                 name: _,
                 value: _,
             } => *id,
+            Expr::AssignTuple {
+                id,
+                targets: _,
+                value: _,
+            } => *id,
             Expr::Binary {
                 id,
                 left: _,
@@ -362,6 +564,12 @@ impl Expr {
                 name: _,
             } => *id,
             Expr::Grouping { id, expression: _ } => *id,
+            Expr::Index {
+                id,
+                object: _,
+                bracket: _,
+                indices: _,
+            } => *id,
             Expr::Literal { id, value: _ } => *id,
             Expr::Logical {
                 id,
@@ -375,7 +583,23 @@ impl Expr {
                 name: _,
                 value: _,
             } => *id,
+            Expr::SetIndex {
+                id,
+                object: _,
+                bracket: _,
+                indices: _,
+                value: _,
+            } => *id,
             Expr::This { id, keyword: _ } => *id,
+            Expr::Tuple { id, elements: _ } => *id,
+            Expr::Range {
+                id,
+                start: _,
+                operator: _,
+                end: _,
+                step: _,
+                inclusive: _,
+            } => *id,
             Expr::Super {
                 id,
                 keyword: _,
@@ -401,7 +625,27 @@ impl Expr {
                 arguments,
                 body: _,
             } => format!("anon/{}", arguments.len()),
+            Expr::Array {
+                id: _,
+                name,
+                bounds,
+                element_type,
+            } => format!(
+                "(vetor {} [{}] {})",
+                name.lexeme,
+                bounds
+                    .iter()
+                    .map(|(lower, upper)| format!("{}..{}", lower.to_string(), upper.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                element_type.lexeme
+            ),
             Expr::Assign { id: _, name, value } => format!("({name:?} = {}", value.to_string()),
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => format!("({:?} = {}", targets, value.to_string()),
             Expr::Binary {
                 id: _,
                 left,
@@ -427,6 +671,12 @@ impl Expr {
             Expr::Grouping { id: _, expression } => {
                 format!("(agrupar {})", (*expression).to_string())
             }
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                indices,
+            } => format!("(indice {} {:?})", object.to_string(), indices),
             Expr::Literal { id: _, value } => format!("{}", value.to_string()),
             Expr::Logical {
                 id: _,
@@ -450,7 +700,37 @@ impl Expr {
                 name.to_string(),
                 value.to_string()
             ),
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket: _,
+                indices,
+                value,
+            } => format!(
+                "(atribuir {} {:?} {})",
+                object.to_string(),
+                indices,
+                value.to_string()
+            ),
             Expr::This { id: _, keyword: _ } => format!("(_objeto)"),
+            Expr::Tuple { id: _, elements } => format!("(tupla {:?})", elements),
+            Expr::Range {
+                id: _,
+                start,
+                operator,
+                end,
+                step,
+                inclusive: _,
+            } => match step {
+                Some(step) => format!(
+                    "({} {} {} passo {})",
+                    operator.lexeme,
+                    start.to_string(),
+                    end.to_string(),
+                    step.to_string()
+                ),
+                None => format!("({} {} {})", operator.lexeme, start.to_string(), end.to_string()),
+            },
             Expr::Super {
                 id: _,
                 keyword: _,
@@ -469,7 +749,37 @@ impl Expr {
         }
     }
 
-    pub fn evaluate(&self, environment: Environment) -> Result<LiteralValue, String> {
+    /// The token an error in this expression points at
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::AnonFunction { paren, .. } => Some(paren),
+            Expr::Array { name, .. }
+            | Expr::Assign { name, .. }
+            | Expr::Get { name, .. }
+            | Expr::Set { name, .. }
+            | Expr::Variable { name, .. } => Some(name),
+            Expr::Binary { operator, .. }
+            | Expr::Logical { operator, .. }
+            | Expr::Range { operator, .. }
+            | Expr::Unary { operator, .. } => Some(operator),
+            Expr::Index { bracket, .. } | Expr::SetIndex { bracket, .. } => Some(bracket),
+            // What was called, rather than the parenthesis
+            Expr::Call { callee, paren, .. } => callee.token().or(Some(paren)),
+            Expr::Grouping { expression, .. } => expression.token(),
+            Expr::This { keyword, .. } => Some(keyword),
+            Expr::Super { method, .. } => Some(method),
+            Expr::AssignTuple { .. } | Expr::Literal { .. } | Expr::Tuple { .. } => None,
+        }
+    }
+
+    pub fn evaluate(&self, environment: Environment) -> Result<LiteralValue, RuntimeError> {
+        self.evaluate_inner(environment).map_err(|error| match self.token() {
+            Some(token) => error.raised_at(Span::of(token)),
+            None => error,
+        })
+    }
+
+    fn evaluate_inner(&self, environment: Environment) -> Result<LiteralValue, RuntimeError> {
         match self {
             Expr::AnonFunction {
                 id: _,
@@ -480,18 +790,35 @@ impl Expr {
                 // We have to clone everything so the borrow checker doesnt get scared about us taking ownership of the values in the Expr
                 let arity = arguments.len();
                 let arguments: Vec<Token> = arguments.iter().map(|t| (*t).clone()).collect();
-                let body: Vec<Box<Stmt>> = body.iter().map(|b| (*b).clone()).collect();
+                let body: Vec<Stmt> = body.iter().map(|b| (*b).clone()).collect();
 
+                let is_generator = contains_yield(&body);
                 let callable_impl = CallableImpl::LoxFunction(LoxFunctionImpl {
                     name: "fun_anonima".to_string(),
                     arity,
                     parent_env: environment.clone(),
-                    params: arguments,
-                    body,
+                    params: Rc::new(arguments),
+                    body: Rc::new(body),
+                    is_generator,
+                    class_name: None,
                 });
 
                 Ok(Callable(callable_impl))
             }
+            Expr::Array {
+                id: _,
+                name,
+                bounds,
+                element_type,
+            } => {
+                let mut bound_values = vec![];
+                for (lower, upper) in bounds {
+                    let lower = lower.evaluate(environment.clone())?;
+                    let upper = upper.evaluate(environment.clone())?;
+                    bound_values.push((lower, upper));
+                }
+                make_array(name, bound_values, element_type)
+            }
             Expr::Assign { id: _, name, value } => {
                 let new_value = (*value).evaluate(environment.clone())?;
                 let assign_success =
@@ -500,8 +827,50 @@ impl Expr {
                 if assign_success {
                     Ok(new_value)
                 } else {
-                    Err(format!("A variável {} não foi declarada", name.lexeme))
+                    Err(format!("A variável {} não foi declarada", name.lexeme).into())
+                }
+            }
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => {
+                let new_value = (*value).evaluate(environment.clone())?;
+                let values = destructure(&new_value, targets.len())?;
+
+                for (target, value) in targets.iter().zip(values) {
+                    if let Expr::Variable { id, name } = target {
+                        if !environment.assign(&name.lexeme, value, *id) {
+                            return Err(format!("A variável {} não foi declarada", name.lexeme).into());
+                        }
+                    }
                 }
+
+                Ok(new_value)
+            }
+            Expr::Range {
+                id: _,
+                start,
+                operator,
+                end,
+                step,
+                inclusive,
+            } => {
+                let start = start.evaluate(environment.clone())?;
+                let end = end.evaluate(environment.clone())?;
+                let step = match step {
+                    Some(step) => step.evaluate(environment.clone())?,
+                    None => Number(1.0),
+                };
+                make_range(start, end, step, operator, *inclusive)
+            }
+            Expr::Tuple { id: _, elements } => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(element.evaluate(environment.clone())?);
+                }
+
+                Ok(Tuple(values))
             }
             Expr::Variable { id: _, name } => match environment.get(&name.lexeme, self.get_id()) {
                 Some(value) => Ok(value.clone()),
@@ -509,67 +878,20 @@ impl Expr {
                     "A variável '{}' não foi declarada no ponto {:?}",
                     name.lexeme,
                     environment.get_distance(self.get_id())
-                )),
+                ).into()),
             },
             Expr::Call {
                 id: _,
                 callee,
-                paren: _,
+                paren,
                 arguments,
             } => {
-                // Look up function definition in environment
-                // let callable_distance = locals.borrow().get(&self.get_id());
                 let callable: LiteralValue = (*callee).evaluate(environment.clone())?;
-                let callable_clone = callable.clone();
-                match callable {
-                    Callable(CallableImpl::LoxFunction(loxfun)) => {
-                        run_lox_function(loxfun, arguments, environment)
-                    }
-                    Callable(CallableImpl::NativeFunction(nativefun)) => {
-                        let mut evaluated_arguments = vec![];
-                        for argument in arguments {
-                            evaluated_arguments.push(argument.evaluate(environment.clone())?);
-                        }
-                        Ok((nativefun.fun)(&evaluated_arguments))
-                    }
-                    LoxClass {
-                        name: _,
-                        methods,
-                        superclass: _,
-                    } => {
-                        let instance = LoxInstance {
-                            class: Box::new(callable_clone.clone()),
-                            fields: Rc::new(RefCell::new(vec![])),
-                        };
-
-                        // Call constructor if present
-                        if let Some(init_method) = methods.get("ini") {
-                            if init_method.arity != arguments.len() {
-                                return Err(
-                                    "O construtor não possui todos os argumentos".to_string()
-                                );
-                            }
-
-                            // let new_env = environment.enclose();
-                            // new_env.define("this".to_string(), instance.clone());
-                            // let mut init_method = init_method.clone();
-                            // init_method.parent_env = new_env.clone();
-                            let mut init_method = init_method.clone();
-                            init_method.parent_env = init_method.parent_env.enclose();
-                            init_method
-                                .parent_env
-                                .define("_objeto".to_string(), instance.clone());
-
-                            if let Err(msg) = run_lox_function(init_method, arguments, environment)
-                            {
-                                return Err(msg);
-                            }
-                        }
-
-                        Ok(instance)
-                    }
-                    other => Err(format!("{} não é uma função", other.to_type())),
+                let mut arg_vals = vec![];
+                for argument in arguments {
+                    arg_vals.push(argument.evaluate(environment.clone())?);
                 }
+                call_value(callable, arg_vals, paren.line_number)
             }
             Expr::Literal { id: _, value } => Ok((*value).clone()),
             Expr::Logical {
@@ -580,7 +902,7 @@ impl Expr {
             } => match operator.token_type {
                 TokenType::Or => {
                     let lhs_value = left.evaluate(environment.clone())?;
-                    let lhs_true = lhs_value.is_truthy();
+                    let lhs_true = lhs_value.is_truthy()?;
                     if lhs_true == True {
                         Ok(lhs_value)
                     } else {
@@ -589,14 +911,14 @@ impl Expr {
                 }
                 TokenType::And => {
                     let lhs_value = left.evaluate(environment.clone())?;
-                    let lhs_true = lhs_value.is_truthy();
+                    let lhs_true = lhs_value.is_truthy()?;
                     if lhs_true == False {
                         Ok(lhs_true)
                     } else {
                         right.evaluate(environment.clone())
                     }
                 }
-                ttype => Err(format!("Token inválido na expressão lógica: {}", ttype)),
+                ttype => Err(format!("Token inválido na expressão lógica: {}", ttype).into()),
             },
             Expr::Get {
                 id: _,
@@ -604,41 +926,7 @@ impl Expr {
                 name,
             } => {
                 let obj_value = object.evaluate(environment.clone())?;
-                // Now obj_value should be a LoxInstance
-                if let LoxInstance { class, fields } = obj_value.clone() {
-                    for (field_name, value) in (*fields.borrow()).iter() {
-                        // Are we getting a field on the object?
-                        if field_name == &name.lexeme {
-                            return Ok(value.clone());
-                        }
-                    }
-                    // Are we getting a method on the object?
-                    // TODO Make a function that finds a method on a class by looking first at the
-                    // class, then at the superclasses in a recursive manner
-
-                    if let LoxClass {
-                        name: _,
-                        methods: _,
-                        superclass: _,
-                    } = class.as_ref()
-                    {
-                        if let Some(method) = find_method(&name.lexeme, *class.clone()) {
-                            let mut callable_impl = method.clone();
-                            let new_env = callable_impl.parent_env.enclose();
-                            new_env.define("_objeto".to_string(), obj_value.clone());
-                            callable_impl.parent_env = new_env;
-                            return Ok(Callable(LoxFunction(callable_impl)));
-                        }
-                    } else {
-                        panic!("Classe inválida");
-                    }
-                    Err(format!("A classe não possui o campo {}", name.lexeme))
-                } else {
-                    Err(format!(
-                        "O tipo não possui a propriedade {}",
-                        obj_value.to_type()
-                    ))
-                }
+                get_property(obj_value, name)
             }
             Expr::Set {
                 id: _,
@@ -647,61 +935,55 @@ impl Expr {
                 value,
             } => {
                 let obj_value = object.evaluate(environment.clone())?;
-                if let LoxInstance { class: _, fields } = obj_value {
-                    let value = value.evaluate(environment.clone())?;
-
-                    let mut idx = 0;
-                    let mut found = false;
-                    for i in 0..(*fields.borrow()).len() {
-                        let field_name = &(*fields.borrow())[i].0;
-                        if field_name == &name.lexeme {
-                            idx = i;
-                            found = true;
-                            break;
-                        }
-                    }
-
-                    if found {
-                        (*fields.borrow_mut())[idx].1 = value.clone();
-                    } else {
-                        (*fields.borrow_mut()).push((name.lexeme.clone(), value));
-                    }
-
-                    Ok(Nil)
-                } else {
-                    Err(format!(
-                        "Não foi possível atribuir o valor no tipo {}",
-                        obj_value.to_type()
-                    ))
+                let value = value.evaluate(environment.clone())?;
+                set_property(obj_value, name, value)
+            }
+            Expr::Index {
+                id: _,
+                object,
+                bracket,
+                indices,
+            } => {
+                let obj_value = object.evaluate(environment.clone())?;
+                let mut index_values = vec![];
+                for index in indices {
+                    index_values.push(index.evaluate(environment.clone())?);
                 }
+                get_index(obj_value, &index_values, bracket)
             }
-            Expr::This { id: _, keyword: _ } => {
-                let this = environment
-                    .get("_objeto", self.get_id())
-                    .expect("Não foi possível identificar o '_objeto'");
-                Ok(this)
+            Expr::SetIndex {
+                id: _,
+                object, //object[indices] = value
+                bracket,
+                indices,
+                value,
+            } => {
+                let obj_value = object.evaluate(environment.clone())?;
+                let mut index_values = vec![];
+                for index in indices {
+                    index_values.push(index.evaluate(environment.clone())?);
+                }
+                let value = value.evaluate(environment.clone())?;
+                set_index(obj_value, &index_values, value, bracket)
             }
+            Expr::This { id: _, keyword } => environment
+                .get("_objeto", self.get_id())
+                .ok_or_else(|| error_at(keyword, "'_objeto' não está disponível aqui")),
             Expr::Super {
                 id: _,
-                keyword: _,
+                keyword,
                 method,
             } => {
-                let superclass = environment.get("super", self.get_id()).expect(&format!(
-                    "Não foi possível acessar a classe 'superior':\n---------------\n{}---------------\n",
-                    environment.dump(0)
-                ));
-
-                let instance = environment.get_this_instance(self.get_id()).unwrap();
+                let missing = || error_at(keyword, "a classe superior não está disponível aqui");
+                let superclass = environment.get("super", self.get_id()).ok_or_else(missing)?;
+                let instance = environment
+                    .get_this_instance(self.get_id())
+                    .ok_or_else(missing)?;
 
                 // let new_env = environment.enclose();
                 // new_env.define("_objeto".to_string(), instance.clone());
 
-                if let LoxClass {
-                    name: _,
-                    methods,
-                    superclass: _,
-                } = superclass.clone()
-                {
+                if let LoxClass { methods, .. } = superclass.clone() {
                     if let Some(method_value) = methods.get(&method.lexeme) {
                         let mut method = method_value.clone();
                         method.parent_env = method.parent_env.enclose();
@@ -714,10 +996,13 @@ impl Expr {
                             "A classe superior não possui o método {} na classe superior {}",
                             method.lexeme,
                             superclass.to_type()
-                        ))
+                        ).into())
                     }
                 } else {
-                    panic!("A classe superior não possui um campo do tipo objeto");
+                    Err(error_at(
+                        keyword,
+                        format!("a classe superior deveria ser uma classe, não {}", superclass.to_type()),
+                    ))
                 }
             }
             Expr::Grouping { id: _, expression } => expression.evaluate(environment),
@@ -727,15 +1012,7 @@ impl Expr {
                 right,
             } => {
                 let right = right.evaluate(environment)?;
-
-                match (&right, operator.token_type) {
-                    (Number(x), TokenType::Minus) => Ok(Number(-x)),
-                    (_, TokenType::Minus) => {
-                        Err(format!("O operador de subtração não foi implementado para {}", right.to_type()))
-                    }
-                    (any, TokenType::Bang) => Ok(any.is_falsy()),
-                    (_, ttype) => Err(format!("{} não é um operador unário válido", ttype)),
-                }
+                unary_op(operator, right)
             }
             Expr::Binary {
                 id: _,
@@ -745,87 +1022,441 @@ impl Expr {
             } => {
                 let left = left.evaluate(environment.clone())?;
                 let right = right.evaluate(environment.clone())?;
+                binary_op(left, operator, right)
+            }
+        }
+    }
 
-                match (&left, operator.token_type, &right) {
-                    (Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
-                    (Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
-                    (Number(x), TokenType::Star, Number(y)) => Ok(Number(x * y)),
-                    (Number(x), TokenType::Slash, Number(y)) => Ok(Number(x / y)),
-                    (Number(x), TokenType::Greater, Number(y)) => {
-                        Ok(LiteralValue::from_bool(x > y))
-                    }
-                    (Number(x), TokenType::GreaterEqual, Number(y)) => {
-                        Ok(LiteralValue::from_bool(x >= y))
-                    }
-                    (Number(x), TokenType::Less, Number(y)) => Ok(LiteralValue::from_bool(x < y)),
-                    (Number(x), TokenType::LessEqual, Number(y)) => {
-                        Ok(LiteralValue::from_bool(x <= y))
-                    }
+    #[allow(dead_code)]
+    pub fn print(&self) {
+        println!("{}", self.to_string());
+    }
+}
 
-                    (StringValue(s), op, Number(n)) => {
-                        if op == TokenType::Plus {
-                            let saida = format!("{} {} ", s, n).trim_start().to_string();
-                            Ok(StringValue(saida))
-                        } else 
-                        {Err(format!("O operador {} não foi definido para texto ou número [texto]", op))}
-                    }
-                    (Number(_), op, StringValue(_)) => {
-                        Err(format!("O operador {} não foi definido para texto ou número [número]", op))
-                    }
+/// Evaluates the condition of `se` or `enquanto`. A value that is neither true nor false is an
+/// error pointing at the condition.
+pub fn condition_holds(condition: &Expr, environment: Environment) -> Result<bool, RuntimeError> {
+    let value = condition.evaluate(environment)?;
+    match value.is_truthy() {
+        Ok(truth) => Ok(truth == True),
+        Err(msg) => Err(match condition.token() {
+            Some(token) => error_at(token, msg),
+            None => msg.into(),
+        }),
+    }
+}
 
-                    (StringValue(s1), TokenType::Plus, StringValue(s2)) => {
-                        Ok(StringValue(format!("{}{}", s1, s2)))
-                    }
+pub fn unary_op(operator: &Token, right: LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    match (&right, operator.token_type) {
+        (Number(x), TokenType::Minus) => Ok(Number(-x)),
+        (DecimalValue(x), TokenType::Minus) => Ok(DecimalValue(x.neg())),
+        (_, TokenType::Minus) => {
+            Err(format!("O operador de subtração não foi implementado para {}", right.to_type()).into())
+        }
+        (any, TokenType::Bang) => Ok(any.is_falsy()?),
+        (any, TokenType::Compl) => Ok(Number(!as_integer(any, operator)? as f64)),
+        (_, ttype) => Err(format!("{} não é um operador unário válido", ttype).into()),
+    }
+}
 
-                    (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
-                    (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
-                    (StringValue(s1), TokenType::Greater, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 > s2))
-                    }
-                    (StringValue(s1), TokenType::GreaterEqual, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 >= s2))
-                    }
-                    (StringValue(s1), TokenType::Less, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 < s2))
-                    }
-                    (StringValue(s1), TokenType::LessEqual, StringValue(s2)) => {
-                        Ok(LiteralValue::from_bool(s1 <= s2))
-                    }
-                    (x, ttype, y) => Err(format!(
-                        "O tipo {} não implementa os operandos {:?} e {:?}",
-                        ttype, x, y
-                    )),
+pub fn binary_op(left: LiteralValue, operator: &Token, right: LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    // Instances may overload the operator with a special method (_soma, _igual, ...)
+    if let LoxInstance { class, fields: _ } = &left {
+        if let Some(special) = operator_method(operator.token_type) {
+            if let Some(method) = find_method(special, *class.clone()) {
+                return call_lox_function(bind_this(method, &left), vec![right], operator.line_number);
+            }
+        }
+        if operator.token_type == TokenType::BangEqual {
+            if let Some(method) = find_method("_igual", *class.clone()) {
+                let line = operator.line_number;
+                let equal = call_lox_function(bind_this(method, &left), vec![right], line)?;
+                return Ok(equal.is_falsy()?);
+            }
+        }
+    }
+
+    // Instances are concatenated through their textual representation
+    match (&left, operator.token_type, &right) {
+        (StringValue(s), TokenType::Plus, LoxInstance { .. }) => {
+            return Ok(StringValue(format!("{}{}", s, to_text(&right, operator.line_number)?)));
+        }
+        (LoxInstance { .. }, TokenType::Plus, StringValue(s)) => {
+            return Ok(StringValue(format!("{}{}", to_text(&left, operator.line_number)?, s)));
+        }
+        _ => (),
+    }
+
+    if let TokenType::Ampersand
+    | TokenType::Bar
+    | TokenType::Xor
+    | TokenType::LessLess
+    | TokenType::GreaterGreater = operator.token_type
+    {
+        return bitwise_binary(&left, operator, &right);
+    }
+
+    // A decimal operand makes the whole operation exact
+    if let (DecimalValue(_), _) | (_, DecimalValue(_)) = (&left, &right) {
+        if let (Some(x), Some(y)) = (as_decimal(&left)?, as_decimal(&right)?) {
+            return Ok(decimal_binary(x, operator.token_type, y)?);
+        }
+    }
+
+    match (&left, operator.token_type, &right) {
+        (Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
+        (Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
+        (Number(x), TokenType::Star, Number(y)) => Ok(Number(x * y)),
+        (Number(x), TokenType::Slash, Number(y)) => Ok(Number(x / y)),
+        (Number(x), TokenType::Greater, Number(y)) => {
+            Ok(LiteralValue::from_bool(x > y))
+        }
+        (Number(x), TokenType::GreaterEqual, Number(y)) => {
+            Ok(LiteralValue::from_bool(x >= y))
+        }
+        (Number(x), TokenType::Less, Number(y)) => Ok(LiteralValue::from_bool(x < y)),
+        (Number(x), TokenType::LessEqual, Number(y)) => {
+            Ok(LiteralValue::from_bool(x <= y))
+        }
+
+        (StringValue(s), op, Number(n)) => {
+            if op == TokenType::Plus {
+                let saida = format!("{} {} ", s, n).trim_start().to_string();
+                Ok(StringValue(saida))
+            } else 
+            {Err(format!("O operador {} não foi definido para texto ou número [texto]", op).into())}
+        }
+        (Number(_), op, StringValue(_)) => {
+            Err(format!("O operador {} não foi definido para texto ou número [número]", op).into())
+        }
+
+        (StringValue(s1), TokenType::Plus, StringValue(s2)) => {
+            Ok(StringValue(format!("{}{}", s1, s2)))
+        }
+        (StringValue(s), TokenType::Plus, DecimalValue(x)) => {
+            Ok(StringValue(format!("{}{}", s, x)))
+        }
+        (DecimalValue(x), TokenType::Plus, StringValue(s)) => {
+            Ok(StringValue(format!("{}{}", x, s)))
+        }
+
+        (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
+        (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
+        (StringValue(s1), TokenType::Greater, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 > s2))
+        }
+        (StringValue(s1), TokenType::GreaterEqual, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 >= s2))
+        }
+        (StringValue(s1), TokenType::Less, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 < s2))
+        }
+        (StringValue(s1), TokenType::LessEqual, StringValue(s2)) => {
+            Ok(LiteralValue::from_bool(s1 <= s2))
+        }
+        (x, ttype, y) => Err(format!(
+            "O tipo {} não implementa os operandos {:?} e {:?}",
+            ttype, x, y
+        ).into()),
+    }
+}
+
+pub fn make_range(
+    start: LiteralValue,
+    end: LiteralValue,
+    step: LiteralValue,
+    operator: &Token,
+    inclusive: bool,
+) -> Result<LiteralValue, RuntimeError> {
+    match (start, end, step) {
+        (Number(_), Number(_), Number(0.0)) => {
+            Err(error_at(operator, "o passo do intervalo não pode ser zero"))
+        }
+        (Number(start), Number(end), Number(step)) => Ok(Range {
+            start,
+            end,
+            step,
+            inclusive,
+        }),
+        (start, end, step) => Err(error_at(
+            operator,
+            format!(
+                "o intervalo precisa de números, não {}, {} e {}",
+                start.to_type(),
+                end.to_type(),
+                step.to_type()
+            ),
+        )),
+    }
+}
+
+/// A new array filled with the default value of its element type
+// Larger arrays would exhaust the memory and abort the program without a message
+const MAX_ARRAY_ELEMENTS: usize = 10_000_000;
+
+pub fn make_array(
+    name: &Token,
+    bounds: Vec<(LiteralValue, LiteralValue)>,
+    element_type: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let mut declared = vec![];
+    let mut size: usize = 1;
+    for (lower, upper) in bounds {
+        let lower = as_bound(lower, &name.lexeme)?;
+        let upper = as_bound(upper, &name.lexeme)?;
+        if upper < lower {
+            return Err(error_at(
+                name,
+                format!("limites inválidos para '{}': [{lower}..{upper}]", name.lexeme),
+            ));
+        }
+        size = (upper as i128 - lower as i128 + 1)
+            .try_into()
+            .ok()
+            .and_then(|length: usize| size.checked_mul(length))
+            .filter(|size| *size <= MAX_ARRAY_ELEMENTS)
+            .ok_or_else(|| {
+                error_at(
+                    name,
+                    format!("'{}' teria mais que {MAX_ARRAY_ELEMENTS} elementos", name.lexeme),
+                )
+            })?;
+        declared.push((lower, upper));
+    }
+
+    Ok(Array {
+        name: name.lexeme.clone(),
+        bounds: declared,
+        elements: heap::values(vec![default_element(&element_type.lexeme); size]),
+    })
+}
+
+/// Calls any callable value with arguments already evaluated, from `line`
+pub fn call_value(callable: LiteralValue, arguments: Vec<LiteralValue>, line: usize) -> Result<LiteralValue, RuntimeError> {
+    let callable_clone = callable.clone();
+    match callable {
+        Callable(CallableImpl::LoxFunction(loxfun)) => call_lox_function(loxfun, arguments, line),
+        Callable(CallableImpl::NativeFunction(nativefun)) => callstack::traced(nativefun.name.clone(), line, || {
+            if !nativefun.arity.accepts(arguments.len()) {
+                return Err(format!(
+                    "A função {} esperava {} argumento(s) mas encontrou {}",
+                    nativefun.name,
+                    nativefun.arity.describe(),
+                    arguments.len()
+                )
+                .into());
+            }
+            (nativefun.fun)(&arguments)
+        }),
+        Callable(CallableImpl::Compiled(closure)) => callstack::traced(closure.proto.name.clone(), line, || {
+            crate::vm::call_closure(&closure, arguments)
+        }),
+        LoxClass { methods, .. } => callstack::traced(call_name(&callable_clone), line, || {
+            let instance = LoxInstance {
+                class: Box::new(callable_clone.clone()),
+                fields: heap::table(HashMap::new()),
+            };
+            initialize_fields(&callable_clone, &instance)?;
+
+            // Call constructor if present
+            if let Some(init_method) = methods.get("ini") {
+                if init_method.arity != arguments.len() {
+                    return Err(RuntimeError::new("O construtor não possui todos os argumentos"));
                 }
+
+                run_lox_function(bind_this(init_method.clone(), &instance), arguments)?;
             }
+
+            Ok(instance)
+        }),
+        other => Err(format!("{} não é uma função", other.to_type()).into()),
+    }
+}
+
+/// `objeto.nome` on any value: fields, methods, properties, statics and built-in methods
+pub fn get_property(obj_value: LiteralValue, name: &Token) -> Result<LiteralValue, RuntimeError> {
+    // Now obj_value should be a LoxInstance
+    if let LoxInstance { class, fields } = obj_value.clone() {
+        // Computed properties take precedence over stored fields
+        if let Some(getter) = find_property(&name.lexeme, &class, false) {
+            return call_lox_function(bind_this(getter, &obj_value), vec![], name.line_number);
+        }
+
+        if let Some(value) = fields.borrow().get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+        if let Some(method) = find_method(&name.lexeme, *class.clone()) {
+            return Ok(Callable(LoxFunction(bind_this(method, &obj_value))));
+        }
+
+        let stored = fields.borrow().keys().cloned().collect::<Vec<String>>();
+        let mut known = member_names(&class);
+        known.extend(stored);
+        match closest_name(&name.lexeme, &known) {
+            Some(similar) => Err(format!(
+                "A classe não possui o campo {}; você quis dizer '{similar}'?",
+                name.lexeme
+            ).into()),
+            None => Err(format!("A classe não possui o campo {}", name.lexeme).into()),
         }
+    } else if let Generator { state, .. } = &obj_value {
+        generator_method(&name.lexeme, state)
+    } else if let StringValue(text) = &obj_value {
+        Ok(bound_string_method(&name.lexeme, text)?)
+    } else if let Namespace {
+        name: module,
+        members,
+    } = &obj_value
+    {
+        match members.get(&name.lexeme) {
+            Some(member) => Ok(member.clone()),
+            None => Err(format!(
+                "O módulo {module} não possui o membro {}",
+                name.lexeme
+            ).into()),
+        }
+    } else if let LoxClass { .. } = obj_value {
+        // Static field or static method, looked up through the superclasses
+        match find_static(&name.lexeme, &obj_value) {
+            Some(value) => Ok(value),
+            None => Err(format!(
+                "A classe {} não possui o membro estático {}",
+                obj_value.to_string(),
+                name.lexeme
+            ).into()),
+        }
+    } else {
+        Err(format!(
+            "O tipo não possui a propriedade {}",
+            obj_value.to_type()
+        ).into())
     }
+}
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        println!("{}", self.to_string());
+/// `objeto.nome = valor` on instances (fields and setters) and classes (statics)
+pub fn set_property(obj_value: LiteralValue, name: &Token, value: LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    if let LoxInstance { class, fields } = obj_value.clone() {
+        if let Some(setter) = find_property(&name.lexeme, &class, true) {
+            call_lox_function(bind_this(setter, &obj_value), vec![value], name.line_number)?;
+            return Ok(Nil);
+        }
+
+        let mut fields = fields.borrow_mut();
+        if let Some(field) = fields.get_mut(&name.lexeme) {
+            *field = value;
+            return Ok(Nil);
+        }
+        if let LoxClass { strict: true, .. } = class.as_ref() {
+            let declared = declared_fields(&class);
+            if !declared.contains(&name.lexeme) {
+                return Err(undeclared_field(&class, &name.lexeme, &declared).into());
+            }
+        }
+        fields.insert(name.lexeme.clone(), value);
+
+        Ok(Nil)
+    } else if let LoxClass { statics, .. } = &obj_value {
+        // Assign where the field was declared, so subclasses share it
+        match find_static_owner(&name.lexeme, &obj_value) {
+            Some(owner) => owner.borrow_mut().insert(name.lexeme.clone(), value),
+            None => statics.borrow_mut().insert(name.lexeme.clone(), value),
+        };
+
+        Ok(Nil)
+    } else {
+        Err(format!(
+            "Não foi possível atribuir o valor no tipo {}",
+            obj_value.to_type()
+        ).into())
     }
 }
 
-pub fn run_lox_function(
+/// `objeto[indices]` on arrays, tuples and texts
+pub fn get_index(obj_value: LiteralValue, index_values: &[LiteralValue], bracket: &Token) -> Result<LiteralValue, RuntimeError> {
+    if let Array {
+        name,
+        bounds,
+        elements,
+    } = obj_value
+    {
+        let offset = array_offset(&name, &bounds, index_values, bracket)?;
+        let value = elements.borrow()[offset].clone();
+        Ok(value)
+    } else if let Tuple(elements) = obj_value {
+        let i = match index_values {
+            [Number(x)] if x.fract() == 0.0 => *x as i64,
+            _ => {
+                return Err(error_at(bracket, "a tupla recebe um único índice inteiro"))
+            }
+        };
+        if i < 0 || i as usize >= elements.len() {
+            return Err(error_at(
+                bracket,
+                format!("índice {i} fora dos limites da tupla de {} elementos", elements.len()),
+            ));
+        }
+        Ok(elements[i as usize].clone())
+    } else if let StringValue(text) = &obj_value {
+        match index_values {
+            [Number(x)] if x.fract() == 0.0 => char_at(text, *x as i64)
+                .map_err(|msg| error_at(bracket, msg)),
+            _ => Err(error_at(bracket, "o texto recebe um único índice inteiro")),
+        }
+    } else {
+        Err(error_at(
+            bracket,
+            format!("o tipo {} não pode ser indexado", obj_value.to_type()),
+        ))
+    }
+}
+
+/// `vetor[indices] = valor`
+pub fn set_index(
+    obj_value: LiteralValue,
+    index_values: &[LiteralValue],
+    value: LiteralValue,
+    bracket: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    if let Array {
+        name,
+        bounds,
+        elements,
+    } = obj_value
+    {
+        let offset = array_offset(&name, &bounds, index_values, bracket)?;
+        (*elements.borrow_mut())[offset] = value.clone();
+        Ok(value)
+    } else {
+        Err(error_at(
+            bracket,
+            format!("não foi possível atribuir um índice no tipo {}", obj_value.to_type()),
+        ))
+    }
+}
+
+/// Runs a function on the call stack, whether the program calls it or an operator, a property
+/// or `saida` does, so a runaway recursion through any of them is a stack overflow error
+pub fn call_lox_function(
     loxfun: LoxFunctionImpl,
-    arguments: &Vec<Expr>,
-    eval_env: Environment,
-) -> Result<LiteralValue, String> {
+    arg_vals: Vec<LiteralValue>,
+    line: usize,
+) -> Result<LiteralValue, RuntimeError> {
+    callstack::traced(callstack::function_name(&loxfun), line, || run_lox_function(loxfun, arg_vals))
+}
+
+/// Runs the body of a function, for a call already on the call stack
+fn run_lox_function(
+    loxfun: LoxFunctionImpl,
+    arg_vals: Vec<LiteralValue>,
+) -> Result<LiteralValue, RuntimeError> {
     // Do some checking (correct number of args?)
-    if arguments.len() != loxfun.arity {
+    if arg_vals.len() != loxfun.arity {
         return Err(format!(
             "A função  {} esperava os arugmentos {} mas encontrou {}",
             loxfun.name,
             loxfun.arity,
-            arguments.len()
-        ));
-    }
-
-    // Evaluate arguments
-    let mut arg_vals = vec![];
-    for arg in arguments {
-        let val = arg.evaluate(eval_env.clone())?;
-        arg_vals.push(val);
+            arg_vals.len()
+        ).into());
     }
 
     let fun_env = loxfun.parent_env.enclose();
@@ -834,25 +1465,26 @@ pub fn run_lox_function(
         fun_env.define(loxfun.params[i].lexeme.clone(), (*val).clone());
     }
 
-    let mut int = Interpreter::with_env(fun_env);
-    for i in 0..(loxfun.body.len()) {
-        let result = int.interpret(vec![&loxfun.body[i]]);
-        if let Err(e) = result {
-            return Err(e);
-        }
-        if let Some(value) = int.specials.get("retorna") {
-            return Ok(value.clone());
-        }
+    if loxfun.is_generator {
+        let state = GeneratorState::new(&loxfun, fun_env);
+        return Ok(Generator {
+            name: loxfun.name,
+            state: Rc::new(RefCell::new(state)),
+        });
     }
 
-    Ok(LiteralValue::Nil)
+    let mut int = Interpreter::with_env(fun_env);
+    match int.interpret(loxfun.body.iter().collect())? {
+        Flow::Return(value) => Ok(value),
+        Flow::Next => Ok(LiteralValue::Nil),
+    }
 }
 
 pub fn find_method(name: &str, class: LiteralValue) -> Option<LoxFunctionImpl> {
     if let LoxClass {
-        name: _,
         methods,
         superclass,
+        ..
     } = class
     {
         if let Some(fun) = methods.get(name) {
@@ -863,16 +1495,508 @@ pub fn find_method(name: &str, class: LiteralValue) -> Option<LoxFunctionImpl> {
         }
         None
     } else {
-        panic!("Não foi possível encontrar os métodos. Aparentemente o tipo não é uma classe");
+        // Only classes have methods
+        None
+    }
+}
+
+/// Operand of a bitwise operator, which only makes sense for whole numbers
+pub fn as_integer(value: &LiteralValue, operator: &Token) -> Result<i64, RuntimeError> {
+    match value {
+        // i64::MAX as f64 rounds up to 2^63, which is already out of range
+        Number(x) if x.fract() == 0.0 && *x >= i64::MIN as f64 && *x < i64::MAX as f64 => Ok(*x as i64),
+        Number(x) if x.fract() == 0.0 => Err(error_at(
+            operator,
+            format!(
+                "o operador '{}' aceita inteiros entre {} e {}, mas recebeu {}",
+                operator.lexeme,
+                i64::MIN,
+                i64::MAX,
+                x
+            ),
+        )),
+        Number(x) => Err(error_at(
+            operator,
+            format!("o operador '{}' exige números inteiros, mas recebeu {}", operator.lexeme, x),
+        )),
+        other => Err(error_at(
+            operator,
+            format!(
+                "o operador '{}' exige números inteiros, mas recebeu um {}",
+                operator.lexeme,
+                other.to_type()
+            ),
+        )),
+    }
+}
+
+fn bitwise_binary(left: &LiteralValue, operator: &Token, right: &LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    let x = as_integer(left, operator)?;
+    let y = as_integer(right, operator)?;
+    let result = match operator.token_type {
+        TokenType::Ampersand => x & y,
+        TokenType::Bar => x | y,
+        TokenType::Xor => x ^ y,
+        TokenType::LessLess | TokenType::GreaterGreater => {
+            if !(0..64).contains(&y) {
+                return Err(error_at(
+                    operator,
+                    format!("o deslocamento deve estar entre 0 e 63, mas recebeu {y}"),
+                ));
+            }
+            if operator.token_type == TokenType::LessLess {
+                x << y
+            } else {
+                x >> y
+            }
+        }
+        other => return Err(format!("{} não é um operador bit a bit", other).into()),
+    };
+    Ok(Number(result as f64))
+}
+
+/// Ordinary numbers taking part in a decimal operation, converted as they are written
+fn as_decimal(value: &LiteralValue) -> Result<Option<Decimal>, String> {
+    match value {
+        DecimalValue(x) => Ok(Some(*x)),
+        Number(x) => Ok(Some(Decimal::from_f64(*x)?)),
+        _ => Ok(None),
+    }
+}
+
+fn decimal_binary(x: Decimal, operator: TokenType, y: Decimal) -> Result<LiteralValue, String> {
+    match operator {
+        TokenType::Plus => Ok(DecimalValue(x.add(y)?)),
+        TokenType::Minus => Ok(DecimalValue(x.sub(y)?)),
+        TokenType::Star => Ok(DecimalValue(x.mul(y)?)),
+        TokenType::Slash => Ok(DecimalValue(x.div(y)?)),
+        TokenType::EqualEqual => Ok(LiteralValue::from_bool(x == y)),
+        TokenType::BangEqual => Ok(LiteralValue::from_bool(x != y)),
+        TokenType::Greater => Ok(LiteralValue::from_bool(x > y)),
+        TokenType::GreaterEqual => Ok(LiteralValue::from_bool(x >= y)),
+        TokenType::Less => Ok(LiteralValue::from_bool(x < y)),
+        TokenType::LessEqual => Ok(LiteralValue::from_bool(x <= y)),
+        other => Err(format!("O operador {} não foi definido para decimais", other)),
+    }
+}
+
+/// The value at `position` in `para x em valor`, or `None` when the iteration is over
+pub fn iteration_value(value: &LiteralValue, position: usize) -> Result<Option<LiteralValue>, RuntimeError> {
+    match value {
+        Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => {
+            // Computed from the start each time, so fractional steps don't accumulate errors
+            let x = start + position as f64 * step;
+            let inside = match (*step > 0.0, *inclusive) {
+                (true, true) => x <= *end,
+                (true, false) => x < *end,
+                (false, true) => x >= *end,
+                (false, false) => x > *end,
+            };
+            Ok(if inside { Some(Number(x)) } else { None })
+        }
+        Tuple(elements) => Ok(elements.get(position).cloned()),
+        Array { elements, .. } => Ok(elements.borrow().get(position).cloned()),
+        // A generator keeps its own position
+        Generator { state, .. } => resume_generator(state)?.next(),
+        other => Err(format!("Não é possível percorrer um {}", other.to_type()).into()),
+    }
+}
+
+/// The generator's state, unless it is the one currently running (`proximo()` from its own body)
+fn resume_generator(
+    state: &Rc<RefCell<GeneratorState>>,
+) -> Result<std::cell::RefMut<'_, GeneratorState>, RuntimeError> {
+    state
+        .try_borrow_mut()
+        .map_err(|_| RuntimeError::new("O gerador já está em execução"))
+}
+
+/// `proximo()` gives the next value, or vazio once exhausted; `terminou()` tells them apart
+fn generator_method(name: &str, state: &Rc<RefCell<GeneratorState>>) -> Result<LiteralValue, RuntimeError> {
+    let state = state.clone();
+    let fun: NativeFn = match name {
+        "proximo" => Rc::new(move |_args| Ok(resume_generator(&state)?.next()?.unwrap_or(Nil))),
+        "terminou" => Rc::new(move |_args| {
+            Ok(LiteralValue::from_bool(resume_generator(&state)?.is_finished()?))
+        }),
+        _ => return Err(format!("O gerador não possui o método {name}").into()),
+    };
+
+    Ok(Callable(NativeFunction(NativeFunctionImpl {
+        name: name.to_string(),
+        arity: Arity::Exactly(0),
+        fun,
+    })))
+}
+
+/// Values of a tuple being unpacked into `count` variables
+pub fn destructure(value: &LiteralValue, count: usize) -> Result<Vec<LiteralValue>, RuntimeError> {
+    match value {
+        Tuple(elements) if elements.len() == count => Ok(elements.clone()),
+        Tuple(elements) => Err(format!(
+            "A desestruturação espera {count} valores, mas a tupla possui {}",
+            elements.len()
+        ).into()),
+        other => Err(format!(
+            "Só é possível desestruturar uma tupla, não um {}",
+            other.to_type()
+        ).into()),
+    }
+}
+
+/// Text shown by `saida` and in concatenations, honouring a user-defined `_texto()`
+pub fn to_text(value: &LiteralValue, line: usize) -> Result<String, RuntimeError> {
+    if let LoxInstance { class, fields: _ } = value {
+        if let Some(method) = find_method("_texto", *class.clone()) {
+            return match call_lox_function(bind_this(method, value), vec![], line)? {
+                StringValue(text) => Ok(text),
+                other => Err(format!(
+                    "O método _texto de '{}' deve retornar um Texto, não {}",
+                    class_name(class),
+                    other.to_type()
+                ).into()),
+            };
+        }
+    }
+
+    Ok(value.to_string())
+}
+
+fn operator_method(operator: TokenType) -> Option<&'static str> {
+    match operator {
+        TokenType::Plus => Some("_soma"),
+        TokenType::Minus => Some("_sub"),
+        TokenType::Star => Some("_mult"),
+        TokenType::Slash => Some("_div"),
+        TokenType::EqualEqual => Some("_igual"),
+        TokenType::Less => Some("_menor"),
+        TokenType::LessEqual => Some("_menor_igual"),
+        TokenType::Greater => Some("_maior"),
+        TokenType::GreaterEqual => Some("_maior_igual"),
+        _ => None,
+    }
+}
+
+/// Checks that the class, including what it inherits, has every method of the contract
+pub fn check_contract(class: &LiteralValue, contract: &LiteralValue) -> Result<(), String> {
+    if let Contract {
+        name: contract_name,
+        methods,
+    } = contract
+    {
+        let mut problems = vec![];
+        for (method, arity) in methods {
+            match find_method(method, class.clone()) {
+                None => problems.push(format!("falta o método {method}/{arity}")),
+                Some(fun) if fun.arity != *arity => problems.push(format!(
+                    "o método {method} deveria receber {arity} parâmetro(s), mas recebe {}",
+                    fun.arity
+                )),
+                Some(_) => (),
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "A classe '{}' não cumpre o contrato '{contract_name}': {}",
+                class_name(class),
+                problems.join("; ")
+            ))
+        }
+    } else {
+        Err(format!("{} não é um contrato", contract.to_type()))
+    }
+}
+
+/// Makes `_objeto` available inside a method, the same way `Expr::Get` does for methods
+pub fn bind_this(method: LoxFunctionImpl, instance: &LiteralValue) -> LoxFunctionImpl {
+    let mut method = method;
+    method.parent_env = method.parent_env.enclose();
+    method
+        .parent_env
+        .define("_objeto".to_string(), instance.clone());
+    if let LoxInstance { class, .. } = instance {
+        method.class_name = Some(class_name(class).to_string());
+    }
+    method
+}
+
+/// Gives a new object the fields its class and superclasses declare, the superclasses' first
+fn initialize_fields(class: &LiteralValue, instance: &LiteralValue) -> Result<(), RuntimeError> {
+    if let LoxClass {
+        superclass,
+        field_values,
+        ..
+    } = class
+    {
+        if let Some(superclass) = superclass {
+            initialize_fields(superclass, instance)?;
+        }
+        if let Some(field_values) = field_values {
+            run_lox_function(bind_this(*field_values.clone(), instance), vec![])?;
+        }
+    }
+    Ok(())
+}
+
+/// The fields declared with `campo` by a class and its superclasses
+fn declared_fields(class: &LiteralValue) -> Vec<String> {
+    match class {
+        LoxClass {
+            fields, superclass, ..
+        } => {
+            let mut declared = fields.clone();
+            if let Some(superclass) = superclass {
+                declared.extend(declared_fields(superclass));
+            }
+            declared
+        }
+        _ => vec![],
+    }
+}
+
+/// Everything an object of `class` answers to besides its stored fields
+fn member_names(class: &LiteralValue) -> Vec<String> {
+    match class {
+        LoxClass {
+            methods,
+            getters,
+            setters,
+            fields,
+            superclass,
+            ..
+        } => {
+            let mut names: Vec<String> = methods
+                .keys()
+                .chain(getters.keys())
+                .chain(setters.keys())
+                .chain(fields.iter())
+                .cloned()
+                .collect();
+            if let Some(superclass) = superclass {
+                names.extend(member_names(superclass));
+            }
+            names
+        }
+        _ => vec![],
+    }
+}
+
+fn undeclared_field(class: &LiteralValue, name: &str, declared: &[String]) -> String {
+    let class = class_name(class);
+    if let Some(similar) = closest_name(name, declared) {
+        return format!("A classe {class} não declara o campo '{name}'; você quis dizer '{similar}'?");
+    }
+    if declared.is_empty() {
+        format!("A classe {class} não declara o campo '{name}' e não declara nenhum campo")
+    } else {
+        format!(
+            "A classe {class} não declara o campo '{name}'; os campos declarados são: {}",
+            declared.join(", ")
+        )
+    }
+}
+
+/// The candidate most like `name`, when it is close enough to be a typo of it
+fn closest_name<'a>(name: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let length = name.chars().count();
+    let tolerance = (length / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        // Replacing every character of a short name is not a typo
+        .filter(|(distance, _)| *distance <= tolerance && *distance < length)
+        .min_by(|(a, x), (b, y)| a.cmp(b).then(x.cmp(y)))
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Characters to insert, delete, replace or swap with the next one to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows for the prefixes of `a` two, one and zero characters shorter
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+pub fn find_property(name: &str, class: &LiteralValue, setter: bool) -> Option<LoxFunctionImpl> {
+    if let LoxClass {
+        superclass,
+        getters,
+        setters,
+        ..
+    } = class
+    {
+        let properties = if setter { setters } else { getters };
+        if let Some(fun) = properties.get(name) {
+            return Some(fun.clone());
+        }
+        match superclass {
+            Some(superclass) => find_property(name, superclass, setter),
+            None => None,
+        }
+    } else {
+        None
+    }
+}
+
+pub fn find_static(name: &str, class: &LiteralValue) -> Option<LiteralValue> {
+    if let LoxClass {
+        superclass,
+        static_methods,
+        statics,
+        ..
+    } = class
+    {
+        if let Some(value) = statics.borrow().get(name) {
+            return Some(value.clone());
+        }
+        if let Some(fun) = static_methods.get(name) {
+            return Some(Callable(LoxFunction(fun.clone())));
+        }
+        match superclass {
+            Some(superclass) => find_static(name, superclass),
+            None => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn find_static_owner(
+    name: &str,
+    class: &LiteralValue,
+) -> Option<Rc<RefCell<HashMap<String, LiteralValue>>>> {
+    if let LoxClass {
+        superclass,
+        statics,
+        ..
+    } = class
+    {
+        if statics.borrow().contains_key(name) {
+            return Some(statics.clone());
+        }
+        match superclass {
+            Some(superclass) => find_static_owner(name, superclass),
+            None => None,
+        }
+    } else {
+        None
     }
 }
 
+fn default_element(element_type: &str) -> LiteralValue {
+    match element_type {
+        "texto" => StringValue("".to_string()),
+        "logico" => False,
+        _ => Number(0.0),
+    }
+}
+
+fn as_bound(value: LiteralValue, name: &str) -> Result<i64, String> {
+    match value {
+        Number(x) if x.fract() == 0.0 => Ok(x as i64),
+        other => Err(format!(
+            "Os limites de '{name}' precisam ser números inteiros, não {}",
+            other.to_string()
+        )),
+    }
+}
+
+fn array_offset(
+    name: &str,
+    bounds: &[(i64, i64)],
+    indices: &[LiteralValue],
+    bracket: &Token,
+) -> Result<usize, RuntimeError> {
+    if indices.len() != bounds.len() {
+        return Err(error_at(
+            bracket,
+            format!("'{name}' possui {} dimensões mas recebeu {} índices", bounds.len(), indices.len()),
+        ));
+    }
+
+    let mut offset = 0;
+    for (dimension, (index, (lower, upper))) in indices.iter().zip(bounds.iter()).enumerate() {
+        let i = match index {
+            Number(x) if x.fract() == 0.0 => *x as i64,
+            other => {
+                return Err(error_at(
+                    bracket,
+                    format!("o índice de '{name}' precisa ser um número inteiro, não {}", other.to_string()),
+                ))
+            }
+        };
+        if i < *lower || i > *upper {
+            let where_ = if bounds.len() > 1 {
+                format!(" na dimensão {}", dimension + 1)
+            } else {
+                "".to_string()
+            };
+            return Err(error_at(
+                bracket,
+                format!("índice {i} fora dos limites de '{name}'{where_}, declarado [{lower}..{upper}]"),
+            ));
+        }
+        offset = offset * (upper - lower + 1) as usize + (i - lower) as usize;
+    }
+
+    Ok(offset)
+}
+
+fn array_to_string(bounds: &[(i64, i64)], elements: &[LiteralValue]) -> String {
+    if bounds.len() <= 1 {
+        let items: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+        return format!("[{}]", items.join(", "));
+    }
+
+    let row_len = elements.len() / (bounds[0].1 - bounds[0].0 + 1) as usize;
+    let rows: Vec<String> = elements
+        .chunks(row_len)
+        .map(|row| array_to_string(&bounds[1..], row))
+        .collect();
+    format!("[{}]", rows.join(", "))
+}
+
 #[cfg(test)]
 mod tests {
     use super::Expr::*;
     use super::*;
     use std::collections::HashMap;
 
+    #[test]
+    fn suggests_the_closest_name() {
+        let names = vec!["nome".to_string(), "idade".to_string(), "endereco".to_string()];
+        assert_eq!(edit_distance("nmoe", "nome"), 1);
+        assert_eq!(edit_distance("", "nome"), 4);
+        assert_eq!(closest_name("nmoe", &names), Some("nome"));
+        assert_eq!(closest_name("idad", &names), Some("idade"));
+        assert_eq!(closest_name("endereço", &names), Some("endereco"));
+        assert_eq!(closest_name("cor", &names), None);
+        assert_eq!(closest_name("x", &["y".to_string()]), None);
+    }
+
     #[test]
     fn pretty_print_ast() {
         let minus_token = Token {
@@ -880,6 +2004,7 @@ mod tests {
             lexeme: "-".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let onetwothree = Literal {
             id: 0,
@@ -897,6 +2022,7 @@ mod tests {
             lexeme: "*".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let ast = Binary {
             id: 3,
@@ -921,6 +2047,7 @@ mod tests {
             lexeme: "-".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let onetwothree = Literal {
             id: 0,
@@ -938,6 +2065,7 @@ mod tests {
             lexeme: "*".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let expr = Binary {
             id: 3,
@@ -0,0 +1,227 @@
+use crate::budget;
+use crate::diagnostic::RuntimeError;
+use crate::environment::Environment;
+use crate::expr::{condition_holds, iteration_value, Expr, LiteralValue, LoxFunctionImpl};
+use crate::interpreter::{Flow, Interpreter};
+use crate::stmt::Stmt;
+
+/// A statement that was entered but not finished when the generator last stopped at `produz`
+enum Frame {
+    Block {
+        statements: Vec<Stmt>,
+        position: usize,
+        env: Environment,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        env: Environment,
+    },
+    ForRange {
+        variable: String,
+        value: LiteralValue,
+        position: usize,
+        body: Box<Stmt>,
+        env: Environment,
+    },
+}
+
+/// Execution state of a function that contains `produz`.
+///
+/// The tree-walking interpreter can't pause in the middle of `interpret`, so the statements
+/// that may reach a `produz` (blocks, `se`, `enquanto` and `para ... em`) are kept on an
+/// explicit stack of frames, and every other statement is still run by an `Interpreter`.
+pub struct GeneratorState {
+    frames: Vec<Frame>,
+    peeked: Option<LiteralValue>,
+    finished: bool,
+}
+
+impl GeneratorState {
+    pub fn new(loxfun: &LoxFunctionImpl, fun_env: Environment) -> Self {
+        Self {
+            frames: vec![Frame::Block {
+                statements: loxfun.body.to_vec(),
+                position: 0,
+                env: fun_env,
+            }],
+            peeked: None,
+            finished: false,
+        }
+    }
+
+    /// Runs until the next `produz`; `None` once the function body is over
+    pub fn next(&mut self) -> Result<Option<LiteralValue>, RuntimeError> {
+        if let Some(value) = self.peeked.take() {
+            return Ok(Some(value));
+        }
+        if self.finished {
+            return Ok(None);
+        }
+
+        match self.resume() {
+            Ok(Some(value)) => Ok(Some(value)),
+            Ok(None) => {
+                self.finish();
+                Ok(None)
+            }
+            Err(msg) => {
+                // A generator that failed can't be resumed
+                self.finish();
+                Err(msg)
+            }
+        }
+    }
+
+    /// Whether the generator has no more values; may run it up to the next `produz`
+    pub fn is_finished(&mut self) -> Result<bool, RuntimeError> {
+        if self.peeked.is_none() && !self.finished {
+            self.peeked = self.next()?;
+        }
+        Ok(self.peeked.is_none())
+    }
+
+    fn finish(&mut self) {
+        self.frames.clear();
+        self.finished = true;
+    }
+
+    fn resume(&mut self) -> Result<Option<LiteralValue>, RuntimeError> {
+        loop {
+            let (stmt, env) = match self.frames.last_mut() {
+                None => return Ok(None),
+                Some(Frame::Block {
+                    statements,
+                    position,
+                    env,
+                }) => {
+                    if *position >= statements.len() {
+                        self.frames.pop();
+                        continue;
+                    }
+                    *position += 1;
+                    (statements[*position - 1].clone(), env.clone())
+                }
+                Some(Frame::While {
+                    condition,
+                    body,
+                    env,
+                }) => {
+                    if !condition_holds(condition, env.clone())? {
+                        self.frames.pop();
+                        continue;
+                    }
+                    (body.as_ref().clone(), env.clone())
+                }
+                Some(Frame::ForRange {
+                    variable,
+                    value,
+                    position,
+                    body,
+                    env,
+                }) => match iteration_value(value, *position)? {
+                    None => {
+                        self.frames.pop();
+                        continue;
+                    }
+                    Some(current) => {
+                        *position += 1;
+                        env.set_at(variable, 0, current);
+                        (body.as_ref().clone(), env.clone())
+                    }
+                },
+            };
+
+            if let Some(value) = self.execute(&stmt, env)? {
+                return Ok(Some(value));
+            }
+        }
+    }
+
+    /// Starts running `stmt`; returns the value when it is a `produz`
+    fn execute(&mut self, stmt: &Stmt, env: Environment) -> Result<Option<LiteralValue>, RuntimeError> {
+        budget::step()?;
+        match stmt {
+            Stmt::Yield { keyword: _, value } => Ok(Some(value.evaluate(env)?)),
+            Stmt::Block { statements } => {
+                self.frames.push(Frame::Block {
+                    statements: statements.clone(),
+                    position: 0,
+                    env: env.enclose(),
+                });
+                Ok(None)
+            }
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => {
+                if condition_holds(predicate, env.clone())? {
+                    self.execute(then, env)
+                } else if let Some(els) = els {
+                    self.execute(els, env)
+                } else {
+                    Ok(None)
+                }
+            }
+            Stmt::WhileStmt { condition, body } => {
+                self.frames.push(Frame::While {
+                    condition: condition.clone(),
+                    body: body.clone(),
+                    env,
+                });
+                Ok(None)
+            }
+            Stmt::ForRange {
+                variable,
+                range,
+                body,
+            } => {
+                let value = range.evaluate(env.clone())?;
+                let env = env.enclose();
+                env.define(variable.lexeme.clone(), LiteralValue::Nil);
+                self.frames.push(Frame::ForRange {
+                    variable: variable.lexeme.clone(),
+                    value,
+                    position: 0,
+                    body: body.clone(),
+                    env,
+                });
+                Ok(None)
+            }
+            Stmt::ReturnStmt { keyword: _, value } => {
+                // `retorna` ends the sequence; its value has nowhere to go
+                if let Some(value) = value {
+                    value.evaluate(env)?;
+                }
+                self.frames.clear();
+                Ok(None)
+            }
+            other => {
+                let mut int = Interpreter::with_env(env);
+                if let Flow::Return(_) = int.interpret(vec![other])? {
+                    self.frames.clear();
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Whether a function body produces values, without looking inside nested functions
+pub fn contains_yield(body: &[Stmt]) -> bool {
+    body.iter().any(stmt_contains_yield)
+}
+
+fn stmt_contains_yield(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Yield { .. } => true,
+        Stmt::Block { statements } => contains_yield(statements),
+        Stmt::IfStmt { then, els, .. } => {
+            stmt_contains_yield(then) || els.as_ref().is_some_and(|els| stmt_contains_yield(els))
+        }
+        Stmt::WhileStmt { body, .. } => stmt_contains_yield(body),
+        Stmt::ForRange { body, .. } => stmt_contains_yield(body),
+        _ => false,
+    }
+}
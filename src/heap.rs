@@ -0,0 +1,467 @@
+//! Cycle collection for the values a program shares. Scopes, objects, arrays and captured
+//! variables live in `Rc`s, which free a value as soon as nothing refers to it, but never free
+//! values that refer to each other: two objects pointing at one another, or a function kept in
+//! the scope it captured. Every such container is registered here when it is made, and once
+//! enough were made since the last collection, `collect` looks for the ones kept alive only by
+//! each other and empties them, which frees the whole cycle.
+//!
+//! It works like the collector of CPython: a container referred to more times than the
+//! registered containers account for is held by the interpreter itself (a variable on the Rust
+//! stack, the VM stack, the global scope), so it and everything it refers to stay. References
+//! the collector can't see, inside native functions and generators, only keep values alive.
+
+use crate::environment::Environment;
+use crate::expr::{CallableImpl, LiteralValue};
+use crate::vm::Closure;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+pub type Fields = RefCell<Vec<(String, LiteralValue)>>;
+pub type Values = RefCell<Vec<LiteralValue>>;
+pub type Table = RefCell<HashMap<String, LiteralValue>>;
+pub type Variable = RefCell<LiteralValue>;
+
+// Collections wait for at least this many new containers, and for as many as survived the
+// last one, so their cost stays proportional to what the program allocates
+const MIN_THRESHOLD: usize = 10_000;
+
+enum Node {
+    Fields(Weak<Fields>),
+    Values(Weak<Values>),
+    Table(Weak<Table>),
+    Scope(Weak<Environment>),
+    Variable(Weak<Variable>),
+    Closure(Weak<Closure>),
+}
+
+// A registered container that is still alive, held while collecting
+enum Live {
+    Fields(Rc<Fields>),
+    Values(Rc<Values>),
+    Table(Rc<Table>),
+    Scope(Rc<Environment>),
+    Variable(Rc<Variable>),
+    Closure(Rc<Closure>),
+}
+
+struct Heap {
+    nodes: Vec<Node>,
+    // Containers made since the last collection
+    made: usize,
+    threshold: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            nodes: Vec::new(),
+            made: 0,
+            threshold: MIN_THRESHOLD,
+        })
+    };
+}
+
+/// Identifies a shared value by where it lives
+pub fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// The variables of a scope
+pub fn fields(fields: Vec<(String, LiteralValue)>) -> Rc<Fields> {
+    let rc = Rc::new(RefCell::new(fields));
+    track(Node::Fields(Rc::downgrade(&rc)));
+    rc
+}
+
+/// The elements of an array
+pub fn values(values: Vec<LiteralValue>) -> Rc<Values> {
+    let rc = Rc::new(RefCell::new(values));
+    track(Node::Values(Rc::downgrade(&rc)));
+    rc
+}
+
+/// The globals, the static fields of a class, or the fields of an object
+pub fn table(table: HashMap<String, LiteralValue>) -> Rc<Table> {
+    let rc = Rc::new(RefCell::new(table));
+    track(Node::Table(Rc::downgrade(&rc)));
+    rc
+}
+
+/// The scope enclosing another one
+pub fn scope(scope: Environment) -> Rc<Environment> {
+    let rc = Rc::new(scope);
+    track(Node::Scope(Rc::downgrade(&rc)));
+    rc
+}
+
+/// A variable of the VM captured by a closure
+pub fn variable(value: LiteralValue) -> Rc<Variable> {
+    let rc = Rc::new(RefCell::new(value));
+    track(Node::Variable(Rc::downgrade(&rc)));
+    rc
+}
+
+/// A function compiled by the VM, with the variables it captured
+pub fn closure(closure: Closure) -> Rc<Closure> {
+    let rc = Rc::new(closure);
+    track(Node::Closure(Rc::downgrade(&rc)));
+    rc
+}
+
+fn track(node: Node) {
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.nodes.push(node);
+        heap.made += 1;
+        heap.made >= heap.threshold
+    });
+    if due {
+        collect();
+    }
+}
+
+/// How many registered containers are still alive
+#[allow(dead_code)]
+pub fn tracked() -> usize {
+    HEAP.with(|heap| {
+        heap.borrow()
+            .nodes
+            .iter()
+            .filter(|node| node.strong_count() > 0)
+            .count()
+    })
+}
+
+/// Frees the containers kept alive only by each other, and returns how many there were
+pub fn collect() -> usize {
+    let nodes = HEAP.with(|heap| std::mem::take(&mut heap.borrow_mut().nodes));
+    let live: Vec<Live> = nodes.iter().filter_map(Node::upgrade).collect();
+    drop(nodes);
+    let index: HashMap<usize, usize> = live
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.address(), i))
+        .collect();
+
+    // What the containers refer to, or None for one in use right now, which can't be looked at
+    let edges: Vec<Option<Vec<usize>>> = live
+        .iter()
+        .map(|node| {
+            let addresses = node.edges()?;
+            Some(addresses.iter().filter_map(|a| index.get(a).copied()).collect())
+        })
+        .collect();
+
+    // References left after taking out the ones between containers come from the interpreter
+    // (minus the one `live` holds)
+    let mut outside: Vec<isize> = live
+        .iter()
+        .map(|node| node.strong_count() as isize - 1)
+        .collect();
+    for &target in edges.iter().flatten().flatten() {
+        outside[target] -= 1;
+    }
+
+    let mut reachable = vec![false; live.len()];
+    let mut pending: Vec<usize> = (0..live.len())
+        .filter(|&i| outside[i] > 0 || edges[i].is_none())
+        .collect();
+    for &i in &pending {
+        reachable[i] = true;
+    }
+    while let Some(i) = pending.pop() {
+        for &target in edges[i].iter().flatten() {
+            if !reachable[target] {
+                reachable[target] = true;
+                pending.push(target);
+            }
+        }
+    }
+
+    let mut garbage = 0;
+    let mut survivors = vec![];
+    for (node, reachable) in live.iter().zip(reachable) {
+        if reachable {
+            survivors.push(node.downgrade());
+        } else {
+            node.clear();
+            garbage += 1;
+        }
+    }
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.threshold = MIN_THRESHOLD.max(survivors.len());
+        heap.made = 0;
+        // Anything registered while collecting stays registered
+        survivors.append(&mut heap.nodes);
+        heap.nodes = survivors;
+    });
+    // Dropping `live` frees the garbage, now that its cycles are broken
+    drop(live);
+    garbage
+}
+
+impl Node {
+    fn upgrade(&self) -> Option<Live> {
+        Some(match self {
+            Node::Fields(weak) => Live::Fields(weak.upgrade()?),
+            Node::Values(weak) => Live::Values(weak.upgrade()?),
+            Node::Table(weak) => Live::Table(weak.upgrade()?),
+            Node::Scope(weak) => Live::Scope(weak.upgrade()?),
+            Node::Variable(weak) => Live::Variable(weak.upgrade()?),
+            Node::Closure(weak) => Live::Closure(weak.upgrade()?),
+        })
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Fields(weak) => weak.strong_count(),
+            Node::Values(weak) => weak.strong_count(),
+            Node::Table(weak) => weak.strong_count(),
+            Node::Scope(weak) => weak.strong_count(),
+            Node::Variable(weak) => weak.strong_count(),
+            Node::Closure(weak) => weak.strong_count(),
+        }
+    }
+}
+
+impl Live {
+    fn address(&self) -> usize {
+        match self {
+            Live::Fields(rc) => address(rc),
+            Live::Values(rc) => address(rc),
+            Live::Table(rc) => address(rc),
+            Live::Scope(rc) => address(rc),
+            Live::Variable(rc) => address(rc),
+            Live::Closure(rc) => address(rc),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Live::Fields(rc) => Rc::strong_count(rc),
+            Live::Values(rc) => Rc::strong_count(rc),
+            Live::Table(rc) => Rc::strong_count(rc),
+            Live::Scope(rc) => Rc::strong_count(rc),
+            Live::Variable(rc) => Rc::strong_count(rc),
+            Live::Closure(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    fn downgrade(&self) -> Node {
+        match self {
+            Live::Fields(rc) => Node::Fields(Rc::downgrade(rc)),
+            Live::Values(rc) => Node::Values(Rc::downgrade(rc)),
+            Live::Table(rc) => Node::Table(Rc::downgrade(rc)),
+            Live::Scope(rc) => Node::Scope(Rc::downgrade(rc)),
+            Live::Variable(rc) => Node::Variable(Rc::downgrade(rc)),
+            Live::Closure(rc) => Node::Closure(Rc::downgrade(rc)),
+        }
+    }
+
+    /// The addresses of the shared values this container refers to
+    fn edges(&self) -> Option<Vec<usize>> {
+        let mut edges = vec![];
+        match self {
+            Live::Fields(rc) => {
+                for (_, value) in rc.try_borrow().ok()?.iter() {
+                    trace(value, &mut edges);
+                }
+            }
+            Live::Values(rc) => {
+                for value in rc.try_borrow().ok()?.iter() {
+                    trace(value, &mut edges);
+                }
+            }
+            Live::Table(rc) => {
+                for value in rc.try_borrow().ok()?.values() {
+                    trace(value, &mut edges);
+                }
+            }
+            Live::Scope(scope) => scope.trace(&mut edges),
+            Live::Variable(rc) => trace(&*rc.try_borrow().ok()?, &mut edges),
+            Live::Closure(closure) => {
+                edges.extend(closure.upvalues.iter().map(address));
+                closure.globals.trace(&mut edges);
+            }
+        }
+        Some(edges)
+    }
+
+    /// Breaks the cycles through this container. Scopes and closures can't change after they
+    /// are made, so every cycle also goes through a container that can be emptied.
+    fn clear(&self) {
+        match self {
+            Live::Fields(rc) => {
+                let _ = rc.try_borrow_mut().map(|mut fields| std::mem::take(&mut *fields));
+            }
+            Live::Values(rc) => {
+                let _ = rc.try_borrow_mut().map(|mut values| std::mem::take(&mut *values));
+            }
+            Live::Table(rc) => {
+                let _ = rc.try_borrow_mut().map(|mut table| std::mem::take(&mut *table));
+            }
+            Live::Variable(rc) => {
+                let _ = rc
+                    .try_borrow_mut()
+                    .map(|mut value| std::mem::replace(&mut *value, LiteralValue::Nil));
+            }
+            Live::Scope(_) | Live::Closure(_) => (),
+        }
+    }
+}
+
+/// Adds the addresses of the shared values `value` holds directly, each once per reference
+fn trace(value: &LiteralValue, edges: &mut Vec<usize>) {
+    match value {
+        LiteralValue::Callable(CallableImpl::LoxFunction(fun)) => fun.parent_env.trace(edges),
+        LiteralValue::Callable(CallableImpl::Compiled(closure)) => edges.push(address(closure)),
+        LiteralValue::LoxClass {
+            methods,
+            superclass,
+            static_methods,
+            statics,
+            getters,
+            setters,
+            field_values,
+            ..
+        } => {
+            for fun in methods
+                .values()
+                .chain(static_methods.values())
+                .chain(getters.values())
+                .chain(setters.values())
+            {
+                fun.parent_env.trace(edges);
+            }
+            if let Some(field_values) = field_values {
+                field_values.parent_env.trace(edges);
+            }
+            if let Some(superclass) = superclass {
+                trace(superclass, edges);
+            }
+            edges.push(address(statics));
+        }
+        LiteralValue::LoxInstance { class, fields } => {
+            trace(class, edges);
+            edges.push(address(fields));
+        }
+        LiteralValue::Array { elements, .. } => edges.push(address(elements)),
+        LiteralValue::Mixin { methods, .. } => {
+            for fun in methods.values() {
+                fun.parent_env.trace(edges);
+            }
+        }
+        LiteralValue::Tuple(values) => {
+            for value in values {
+                trace(value, edges);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(fields: Vec<(String, LiteralValue)>) -> LiteralValue {
+        LiteralValue::LoxInstance {
+            class: Box::new(LiteralValue::Nil),
+            fields: table(fields.into_iter().collect()),
+        }
+    }
+
+    fn link(from: &LiteralValue, to: &LiteralValue) {
+        if let LiteralValue::LoxInstance { fields, .. } = from {
+            fields.borrow_mut().insert("outro".to_string(), to.clone());
+        }
+    }
+
+    #[test]
+    fn frees_objects_that_refer_to_each_other() {
+        collect();
+        let a = object(vec![]);
+        let b = object(vec![]);
+        link(&a, &b);
+        link(&b, &a);
+        let LiteralValue::LoxInstance { fields, .. } = &a else { unreachable!() };
+        let first = Rc::downgrade(fields);
+
+        // Still used by the test
+        collect();
+        assert!(first.upgrade().is_some());
+
+        drop((a, b));
+        assert!(first.upgrade().is_some());
+        collect();
+        assert!(first.upgrade().is_none());
+    }
+
+    #[test]
+    fn keeps_what_is_reachable_from_outside() {
+        collect();
+        let kept = object(vec![]);
+        let ring = object(vec![("n".to_string(), LiteralValue::Number(1.0))]);
+        link(&ring, &ring);
+        link(&kept, &ring);
+        drop(ring);
+
+        collect();
+        let LiteralValue::LoxInstance { fields, .. } = &kept else { unreachable!() };
+        let ring = fields.borrow()["outro"].clone();
+        let LiteralValue::LoxInstance { fields, .. } = &ring else { unreachable!() };
+        assert_eq!(fields.borrow()["n"], LiteralValue::Number(1.0));
+    }
+
+    #[test]
+    fn allocates_millions_of_cycles_in_bounded_memory() {
+        collect();
+        let mut most = 0;
+        for i in 0..2_000_000 {
+            let a = object(vec![("n".to_string(), LiteralValue::Number(i as f64))]);
+            let b = object(vec![]);
+            link(&a, &b);
+            link(&b, &a);
+            if i % 100_000 == 0 {
+                most = most.max(tracked());
+            }
+        }
+        collect();
+        assert!(most <= 2 * MIN_THRESHOLD, "{most} objetos vivos");
+        assert_eq!(tracked(), 0);
+    }
+
+    #[test]
+    fn runs_a_long_loop_of_a_program_in_bounded_memory() {
+        use crate::interpreter::{Interpreter, MAX_DOC_BYTES};
+        use crate::parser::Parser;
+        use crate::resolver::Resolver;
+        use crate::scanner::Scanner;
+
+        let source = "
+            classe No { ini(n) { _objeto.n = n; } }
+            var i = 0;
+            enquanto (i < 100000) {
+                var a = No(i);
+                a.eu = a;
+                fun f() { retorna a; }
+                a.f = f;
+                i = i + 1;
+            }
+        ";
+        collect();
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&stmts.iter().collect()).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.resolve(locals);
+        interpreter.interpret(stmts.iter().collect()).unwrap();
+
+        // Without collections each iteration would leave an object, a scope and a function
+        assert!(tracked() <= 2 * MIN_THRESHOLD, "{} objetos vivos", tracked());
+        assert!(interpreter.doc.len() <= MAX_DOC_BYTES + 1024, "doc.md com {} bytes", interpreter.doc.len());
+    }
+}
@@ -1,5 +1,10 @@
+use crate::budget::{self, Limits};
+use crate::diagnostic::{RuntimeError, Span};
 use crate::environment::Environment;
-use crate::expr::{CallableImpl, LiteralValue, LoxFunctionImpl, NativeFunctionImpl};
+use crate::generator::contains_yield;
+use crate::heap;
+use crate::expr::{check_contract, condition_holds, destructure, iteration_value, to_text, Arity, CallableImpl, LiteralValue, LoxFunctionImpl, NativeFunctionImpl};
+use crate::resolver::LocalSlot;
 use crate::scanner::Token;
 use crate::stmt::Stmt;
 use std::collections::HashMap;
@@ -8,10 +13,26 @@ use std::io::Write;
 use std::process::Command;
 use std::rc::Rc;
 
+// doc.md follows the statements as they run, so it stops growing at this size and long loops
+// run in bounded memory
+pub const MAX_DOC_BYTES: usize = 1 << 20;
+
 pub struct Interpreter {
-    pub specials: HashMap<String, LiteralValue>,
     pub environment: Environment,
     pub doc: String,
+    // Line breaks in `doc`, so numbering a new flowchart node doesn't rescan it
+    doc_breaks: usize,
+    // Steps, time and output a program started by `run` may use
+    pub limits: Limits,
+}
+
+/// How a run of statements ended: by reaching the end, or by `retorna`, which unwinds
+/// every enclosing block and loop up to the function call
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Flow {
+    Next,
+    Return(LiteralValue),
 }
 
 impl Interpreter {
@@ -20,27 +41,55 @@ impl Interpreter {
         doc.push_str("flowchart TD\n");
         doc.push_str("L3@{ shape: circle, label: \"início\"}\n");
         Self {
-            specials: HashMap::new(),
             environment: Environment::new(HashMap::new()),
+            doc_breaks: doc.matches('\n').count(),
             doc,
+            limits: Limits::default(),
         }
     }
 
+    /// An interpreter whose programs are stopped when they go over `limits`
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
     pub fn doc(&mut self,) {
         //gera doc
-        let doc = format!("```mermaid\n{}\n```", self.doc);
+        let mut doc = format!("```mermaid\n{}\n", self.doc);
+        if self.doc.len() >= MAX_DOC_BYTES {
+            doc.push_str(&format!("%% fluxo interrompido depois de {MAX_DOC_BYTES} bytes\n"));
+        }
+        doc.push_str("```");
         Self::exporttofile("./doc.md", doc);
     }
 
-    pub fn resolve(&mut self, locals: HashMap<usize, usize>) {
+    /// Makes the command line arguments after the program available to `argumentos()`
+    pub fn define_arguments(&mut self, arguments: &[String]) {
+        let values: Vec<LiteralValue> = arguments
+            .iter()
+            .map(|a| LiteralValue::StringValue(a.clone()))
+            .collect();
+        let fun = NativeFunctionImpl {
+            name: "argumentos".to_string(),
+            arity: Arity::Exactly(0),
+            fun: Rc::new(move |_args: &Vec<LiteralValue>| Ok(LiteralValue::Tuple(values.clone()))),
+        };
+        self.environment.define(
+            "argumentos".to_string(),
+            LiteralValue::Callable(CallableImpl::NativeFunction(fun)),
+        );
+    }
+
+    pub fn resolve(&mut self, locals: HashMap<usize, LocalSlot>) {
         self.environment.resolve(locals);
     }
 
     pub fn with_env(env: Environment) -> Self {
         Self {
-            specials: HashMap::new(),
             environment: env,
             doc: String::new(),
+            doc_breaks: 0,
+            limits: Limits::default(),
         }
     }
 
@@ -48,209 +97,452 @@ impl Interpreter {
     pub fn for_anon(parent: Environment) -> Self {
         let env = parent.enclose();
         Self {
-            specials: HashMap::new(),
             environment: env,
             doc: String::new(),
+            doc_breaks: 0,
+            limits: Limits::default(),
         }
     }
 
-    pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<(), String> {
+    /// Runs a whole program, counting its steps, time and output against the limits from zero
+    pub fn run(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        budget::start(self.limits);
+        self.interpret(stmts.iter().collect())?;
+        Ok(())
+    }
+
+    pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<Flow, RuntimeError> {
         for stmt in stmts {
-            match stmt {
-                Stmt::Expression { expression } => {
-                    expression.evaluate(self.environment.clone())?;                    
-                    let linha = self.doc.lines().count();
-                    let mut doc = format!("L{}[\"{}\"]\n", linha+1, expression.to_string());
-                    self.doc.push_str(doc.as_str());
+            match self.execute(stmt) {
+                Ok(Flow::Next) => (),
+                Err(error) => {
+                    // Errors raised by the statement itself point at it
+                    return Err(match stmt.token() {
+                        Some(token) => error.raised_at(Span::of(token)),
+                        None => error,
+                    });
                 }
-                Stmt::Print { expression } => {
-                    let value = expression.evaluate(self.environment.clone())?;
-                    let value = value.to_string();
-                    println!("{}", value.clone());                    
-                    let linha = self.doc.lines().count();
-                    let mut doc = format!("L{}@{{ shape: doc, label: \"{}\"}}\n", linha+2, expression.to_string());
-                    doc.push_str(format!("L{o} --> L{d}\n", o = linha, d = linha+2).as_str());
-                    self.doc.push_str(&doc);
+                flow => return flow,
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<Flow, RuntimeError> {
+        budget::step()?;
+        match stmt {
+            Stmt::Expression { expression } => {
+                expression.evaluate(self.environment.clone())?;                    
+                let linha = self.doc_lines();
+                let mut doc = format!("L{}[\"{}\"]\n", linha+1, expression.to_string());
+                self.push_doc(doc.as_str());
+            }
+            Stmt::Print { expression } => {
+                let value = expression.evaluate(self.environment.clone())?;
+                let line = expression.token().map_or(0, |token| token.line_number);
+                let value = to_text(&value, line)?;
+                budget::output(value.len() + 1)?;
+                println!("{}", value.clone());                    
+                let linha = self.doc_lines();
+                let mut doc = format!("L{}@{{ shape: doc, label: \"{}\"}}\n", linha+2, expression.to_string());
+                doc.push_str(format!("L{o} --> L{d}\n", o = linha, d = linha+2).as_str());
+                self.push_doc(&doc);
+            }
+            Stmt::Limpar { expression } => {
+                let valor = expression.evaluate(self.environment.clone())?;
+                clear_screen(&valor)?;
+
+                let linha = self.doc_lines();
+                let mut doc = format!("L{}@{{ shape: curv-trap, label: \"limpar\"}}", linha+1);
+                doc.push_str(format!("L{o} --> L{d}", o = linha, d = linha+1).as_str());
+                self.push_doc(&doc);
+
+            }
+            Stmt::Var { name, initializer } => {
+                let valor = initializer.evaluate(self.environment.clone())?;
+                self.environment.define(name.lexeme.clone(), valor.clone());
+
+                let linha = self.doc_lines();
+                let mut doc = format!("L{}@{{ shape: notch-rect, label: \"{var} = {valor}\"}}\n", linha+2, var = name.lexeme.clone(), valor = valor.to_string());
+                doc.push_str(format!("L{o} --> L{d}\n", o = linha+1, d = linha+2).as_str());
+                self.push_doc(&doc);
+            }
+            Stmt::VarTuple { names, initializer } => {
+                let valor = initializer.evaluate(self.environment.clone())?;
+                let values = destructure(&valor, names.len())?;
+                for (name, value) in names.iter().zip(values) {
+                    self.environment.define(name.lexeme.clone(), value);
                 }
-                Stmt::Limpar { expression } => {
-                    let saida = expression.evaluate(self.environment.clone())?
-                        .to_string()
-                        .replace("\\n", "\n")
-                        .replace("\\t", "\t")
-                        ;
-                    // print!("{}[2J", 27 as char); //limpa a tela
-                    print!("{esc}[2J{esc}[1;1H\n", esc = 27 as char);//volta o cursor;
-                    print!("[Fe Ferrugem vs 0.1]\nPortugol reescrito em Rust\n\n{}\n-------------------------------\n", saida);
-                                 
-                    let linha = self.doc.lines().count();
-                    let mut doc = format!("L{}@{{ shape: curv-trap, label: \"limpar\"}}", linha+1);
-                    doc.push_str(format!("L{o} --> L{d}", o = linha, d = linha+1).as_str());
-                    self.doc.push_str(&doc);
 
+                let linha = self.doc_lines();
+                let nomes: Vec<String> = names.iter().map(|n| n.lexeme.clone()).collect();
+                let mut doc = format!("L{}@{{ shape: notch-rect, label: \"({var}) = {valor}\"}}\n", linha+2, var = nomes.join(", "), valor = valor.to_string());
+                doc.push_str(format!("L{o} --> L{d}\n", o = linha+1, d = linha+2).as_str());
+                self.push_doc(&doc);
+            }
+            Stmt::Block { statements } => {
+                let new_environment = self.environment.enclose();
+
+                //     Environment::new();
+                // new_environment.enclosing = Some(Box::new(self.environment.clone()));
+                let old_environment = self.environment.clone();
+                self.environment = new_environment;
+                let block_result =
+                    self.interpret((*statements).iter().collect());
+                self.environment = old_environment;
+                // self.environment = self.environment.enclosing.unwrap();
+                if let Flow::Return(value) = block_result? {
+                    return Ok(Flow::Return(value));
                 }
-                Stmt::Var { name, initializer } => {
-                    let valor = initializer.evaluate(self.environment.clone())?;
-                    self.environment.define(name.lexeme.clone(), valor.clone());
-
-                    let linha = self.doc.lines().count();
-                    let mut doc = format!("L{}@{{ shape: notch-rect, label: \"{var} = {valor}\"}}\n", linha+2, var = name.lexeme.clone(), valor = valor.to_string());
-                    doc.push_str(format!("L{o} --> L{d}\n", o = linha+1, d = linha+2).as_str());
-                    self.doc.push_str(&doc);
+
+                let linha = self.doc_lines();
+                let mut doc = format!("L{}@{{ shape: lin-rect, label: \"subprocesso\"}}\n", linha+1);
+                doc.push_str(format!("L{o} --> L{d}\n", o = linha+1, d = linha+2).as_str());
+                self.push_doc(&doc);
+            }
+            Stmt::Class {
+                name,
+                methods,
+                fields,
+                strict,
+                superclass,
+                static_methods,
+                static_fields,
+                getters,
+                setters,
+                contracts,
+                mixins,
+            } => {
+                let mut methods_map = HashMap::new();
+
+                // Insert the methods of the superclass into the methods of this class
+                let superclass_value;
+                if let Some(superclass) = superclass {
+                    let superclass = superclass.evaluate(self.environment.clone())?;
+                    if let LiteralValue::LoxClass { .. } = superclass {
+                        superclass_value = Some(Box::new(superclass));
+                    } else {
+                        return Err(format!(
+                            "O objeto superior precisa ser uma classe, não um {}",
+                            superclass.to_type()
+                        ).into());
+                    }
+                } else {
+                    superclass_value = None;
                 }
-                Stmt::Block { statements } => {
-                    let new_environment = self.environment.enclose();
-
-                    //     Environment::new();
-                    // new_environment.enclosing = Some(Box::new(self.environment.clone()));
-                    let old_environment = self.environment.clone();
-                    self.environment = new_environment;
-                    let block_result =
-                        self.interpret((*statements).iter().map(|b| b.as_ref()).collect());
-                    self.environment = old_environment;
-                    // self.environment = self.environment.enclosing.unwrap();
-                    block_result?;
-
-                    let linha = self.doc.lines().count();
-                    let mut doc = format!("L{}@{{ shape: lin-rect, label: \"subprocesso\"}}\n", linha+1);
-                    doc.push_str(format!("L{o} --> L{d}\n", o = linha+1, d = linha+2).as_str());
-                    self.doc.push_str(&doc);
+
+                let mut contract_values = vec![];
+                for contract in contracts {
+                    let contract = contract.evaluate(self.environment.clone())?;
+                    if let LiteralValue::Contract { .. } = contract {
+                        contract_values.push(contract);
+                    } else {
+                        return Err(format!(
+                            "A classe {} só pode implementar contratos, não um {}",
+                            name.lexeme,
+                            contract.to_type()
+                        ).into());
+                    }
                 }
-                Stmt::Class {
-                    name,
-                    methods,
-                    superclass,
-                } => {
-                    let mut methods_map = HashMap::new();
-
-                    // Insert the methods of the superclass into the methods of this class
-                    let superclass_value;
-                    if let Some(superclass) = superclass {
-                        let superclass = superclass.evaluate(self.environment.clone())?;
-                        if let LiteralValue::LoxClass { .. } = superclass {
-                            superclass_value = Some(Box::new(superclass));
-                        } else {
-                            return Err(format!(
-                                "O objeto superior precisa ser uma classe, não um {}",
-                                superclass.to_type()
-                            ));
+
+                // Mixin methods come before the superclass and after the class's own
+                // methods; two mixins offering the same method is an error unless the
+                // class defines it itself
+                let mut contributed_by: HashMap<String, String> = HashMap::new();
+                for mixin in mixins {
+                    let mixin = mixin.evaluate(self.environment.clone())?;
+                    if let LiteralValue::Mixin {
+                        name: mixin_name,
+                        methods: mixin_methods,
+                    } = mixin
+                    {
+                        for (method_name, method) in mixin_methods {
+                            let overridden = methods.iter().any(|m| {
+                                matches!(m, Stmt::Function { name, .. } if name.lexeme == method_name)
+                            });
+                            if let Some(previous) = contributed_by.get(&method_name) {
+                                if !overridden {
+                                    return Err(format!(
+                                        "O método '{method_name}' da classe {} é ambíguo: definido nos mixins {previous} e {mixin_name}",
+                                        name.lexeme
+                                    ).into());
+                                }
+                            }
+                            contributed_by.insert(method_name.clone(), mixin_name.clone());
+                            methods_map.insert(method_name, method);
                         }
                     } else {
-                        superclass_value = None;
+                        return Err(format!(
+                            "A classe {} só pode usar mixins, não um {}",
+                            name.lexeme,
+                            mixin.to_type()
+                        ).into());
                     }
+                }
 
-                    self.environment
-                        .define(name.lexeme.clone(), LiteralValue::Nil);
+                self.environment
+                    .define(name.lexeme.clone(), LiteralValue::Nil);
 
-                    self.environment = self.environment.enclose();
-                    if let Some(sc) = superclass_value.clone() {
-                        self.environment.define("super".to_string(), *sc);
+                self.environment = self.environment.enclose();
+                if let Some(sc) = superclass_value.clone() {
+                    self.environment.define("super".to_string(), *sc);
+                }
+
+                for method in methods {
+                    if let Stmt::Function {
+                        name,
+                        params: _,
+                        body: _,
+                    } = method
+                    {
+                        let function = self.make_function(method);
+                        methods_map.insert(name.lexeme.clone(), function);
+                    } else {
+                        panic!(
+                            "O método da classe precisa ser uma função"
+                        );
                     }
+                }
 
-                    for method in methods {
-                        if let Stmt::Function {
-                            name,
-                            params: _,
-                            body: _,
-                        } = method.as_ref()
-                        {
-                            let function = self.make_function(method);
-                            methods_map.insert(name.lexeme.clone(), function);
-                        } else {
-                            panic!(
-                                "O método da classe precisa ser uma função"
-                            );
-                        }
+                let mut static_methods_map = HashMap::new();
+                for method in static_methods {
+                    if let Stmt::Function { name, .. } = method {
+                        let function = self.make_function(method);
+                        static_methods_map.insert(name.lexeme.clone(), function);
                     }
+                }
 
-                    let klass = LiteralValue::LoxClass {
-                        name: name.lexeme.clone(),
-                        methods: methods_map,
-                        superclass: superclass_value,
-                    };
+                let mut getters_map = HashMap::new();
+                for getter in getters {
+                    if let Stmt::Function { name, .. } = getter {
+                        getters_map.insert(name.lexeme.clone(), self.make_function(getter));
+                    }
+                }
 
-                    if !self.environment.assign_global(&name.lexeme, klass) {
-                        return Err(format!("A definição da classe falhou para {}", name.lexeme));
+                let mut setters_map = HashMap::new();
+                for setter in setters {
+                    if let Stmt::Function { name, .. } = setter {
+                        setters_map.insert(name.lexeme.clone(), self.make_function(setter));
                     }
+                }
+
+                // The initializers of the declared fields run on each new object, like a method
+                let field_values = (!fields.is_empty()).then(|| Box::new(LoxFunctionImpl {
+                    name: "campos".to_string(),
+                    arity: 0,
+                    parent_env: self.environment.clone(),
+                    params: Rc::new(vec![]),
+                    body: Rc::new(
+                        fields
+                            .iter()
+                            .map(|(_, initializer)| initializer.clone())
+                            .collect(),
+                    ),
+                    is_generator: false,
+                    class_name: None,
+                }));
 
-                    self.environment = *self.environment.enclosing.clone().unwrap();
+                let mut statics = HashMap::new();
+                for (field, initializer) in static_fields {
+                    match initializer.evaluate(self.environment.clone()) {
+                        Ok(value) => statics.insert(field.lexeme.clone(), value),
+                        Err(msg) => {
+                            self.environment = self.environment.enclosing.as_deref().cloned().unwrap();
+                            return Err(msg);
+                        }
+                    };
                 }
-                Stmt::IfStmt {
-                    predicate,
-                    then,
-                    els,
-                } => {
-                    let truth_value = predicate.evaluate(self.environment.clone())?;
-                    if truth_value.is_truthy() == LiteralValue::True {
-                        let statements = vec![then.as_ref()];
-                        self.interpret(statements)?;
-                    } else if let Some(els_stmt) = els {
-                        let statements = vec![els_stmt.as_ref()];
-                        self.interpret(statements)?;
+
+                let klass = LiteralValue::LoxClass {
+                    name: name.lexeme.clone(),
+                    methods: methods_map,
+                    superclass: superclass_value,
+                    static_methods: static_methods_map,
+                    statics: heap::table(statics),
+                    getters: getters_map,
+                    setters: setters_map,
+                    fields: fields.iter().map(|(field, _)| field.lexeme.clone()).collect(),
+                    field_values,
+                    strict: *strict,
+                };
+
+                for contract in contract_values.iter() {
+                    if let Err(msg) = check_contract(&klass, contract) {
+                        self.environment = self.environment.enclosing.as_deref().cloned().unwrap();
+                        return Err(msg.into());
                     }
                 }
-                Stmt::WhileStmt { condition, body } => {
-                    let mut flag = condition.evaluate(self.environment.clone())?;
-                    while flag.is_truthy() == LiteralValue::True {
-                        let statements = vec![body.as_ref()];
-                        self.interpret(statements)?;
-                        flag = condition.evaluate(self.environment.clone())?;
+
+                self.environment = self.environment.enclosing.as_deref().cloned().unwrap();
+                self.environment.redefine(&name.lexeme, klass);
+            }
+            Stmt::Mixin { name, methods } => {
+                self.environment = self.environment.enclose();
+                let mut methods_map = HashMap::new();
+                for method in methods {
+                    if let Stmt::Function { name, .. } = method {
+                        methods_map.insert(name.lexeme.clone(), self.make_function(method));
                     }
                 }
-                Stmt::Function {
-                    name,
-                    params: _,
-                    body: _,
-                } => {
-                    let callable = self.make_function(stmt);
-                    let fun = LiteralValue::Callable(CallableImpl::LoxFunction(callable));
-                    self.environment.define(name.lexeme.clone(), fun);
-                }
-                Stmt::CmdFunction { name, cmd } => {
-                    // Return a callable that runs a shell command, captures the stdout and returns
-                    // it in a String
+                self.environment = self.environment.enclosing.as_deref().cloned().unwrap();
 
-                    let cmd = cmd.clone();
-                    let local_fn = move |_args: &Vec<LiteralValue>| {
-                        let cmd = cmd.clone();
-                        let parts = cmd.split(" ").collect::<Vec<&str>>();
-                        let mut command = Command::new(parts[0].replace("\"", ""));
-                        for part in parts[1..].iter() {
-                            command.arg(part.replace("\"", ""));
-                        }
-                        let output = command.output().expect("Falha ao rodar o comando externo");
+                let mixin = LiteralValue::Mixin {
+                    name: name.lexeme.clone(),
+                    methods: methods_map,
+                };
+                self.environment.define(name.lexeme.clone(), mixin);
+            }
+            Stmt::Contract { name, methods } => {
+                let contract = LiteralValue::Contract {
+                    name: name.lexeme.clone(),
+                    methods: methods
+                        .iter()
+                        .map(|(method, arity)| (method.lexeme.clone(), *arity))
+                        .collect(),
+                };
+                self.environment.define(name.lexeme.clone(), contract);
+            }
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => {
+                let flow = if condition_holds(predicate, self.environment.clone())? {
+                    self.interpret(vec![then.as_ref()])?
+                } else if let Some(els_stmt) = els {
+                    self.interpret(vec![els_stmt.as_ref()])?
+                } else {
+                    Flow::Next
+                };
+                if let Flow::Return(value) = flow {
+                    return Ok(Flow::Return(value));
+                }
+            }
+            Stmt::WhileStmt { condition, body } => {
+                while condition_holds(condition, self.environment.clone())? {
+                    let statements = vec![body.as_ref()];
+                    if let Flow::Return(value) = self.interpret(statements)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            Stmt::ForRange {
+                variable,
+                range,
+                body,
+            } => {
+                // Bounds are evaluated only once, before the first iteration
+                let valor = range.evaluate(self.environment.clone())?;
 
+                let linha = self.doc_lines();
+                let mut doc = format!("L{}{{{{\"para {var} em {valor}\"}}}}\n", linha+2, var = variable.lexeme, valor = valor.to_string());
+                doc.push_str(format!("L{o} --> L{d}\n", o = linha+1, d = linha+2).as_str());
+                self.push_doc(&doc);
 
-                        return LiteralValue::StringValue(
-                            std::str::from_utf8(output.stdout.as_slice())
-                                .unwrap()
-                                .to_string(),
-                        );
+                let old_environment = self.environment.clone();
+                self.environment = self.environment.enclose();
+                self.environment.define(variable.lexeme.clone(), LiteralValue::Nil);
+                let mut position = 0;
+                let loop_result = loop {
+                    let current = match iteration_value(&valor, position) {
+                        Ok(Some(current)) => current,
+                        Ok(None) => break Ok(Flow::Next),
+                        Err(msg) => break Err(msg),
                     };
-
-                    let fun_val =
-                        LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
-                            name: name.lexeme.clone(),
-                            arity: 0,
-                            fun: Rc::new(local_fn),
-                        }));
-                    self.environment.define(name.lexeme.clone(), fun_val);
+                    self.environment.set_at(&variable.lexeme, 0, current);
+                    match self.interpret(vec![body.as_ref()]) {
+                        Ok(Flow::Next) => (),
+                        other => break other,
+                    }
+                    position += 1;
+                };
+                self.environment = old_environment;
+                if let Flow::Return(value) = loop_result? {
+                    return Ok(Flow::Return(value));
                 }
-                Stmt::ReturnStmt { keyword: _, value } => {
-                    let eval_val;
-                    if let Some(value) = value {
-                        eval_val = value.evaluate(self.environment.clone())?;
-                    } else {
-                        eval_val = LiteralValue::Nil;
+
+                let linha_fim = self.doc_lines();
+                self.push_doc(&format!("L{o} -->|próximo| L{d}\n", o = linha_fim, d = linha+2));
+            }
+            Stmt::Function {
+                name,
+                params: _,
+                body: _,
+            } => {
+                let callable = self.make_function(stmt);
+                let fun = LiteralValue::Callable(CallableImpl::LoxFunction(callable));
+                self.environment.define(name.lexeme.clone(), fun);
+            }
+            Stmt::CmdFunction { name, cmd } => {
+                // Return a callable that runs a shell command, captures the stdout and returns
+                // it in a String
+
+                let cmd = cmd.clone();
+                let local_fn = move |_args: &Vec<LiteralValue>| {
+                    let cmd = cmd.clone();
+                    let parts = cmd.split(" ").collect::<Vec<&str>>();
+                    let mut command = Command::new(parts[0].replace("\"", ""));
+                    for part in parts[1..].iter() {
+                        command.arg(part.replace("\"", ""));
                     }
-                    self.specials.insert("retorna".to_string(), eval_val);
-                    self.doc.push_str(&"fim");
+                    let output = command
+                        .output()
+                        .map_err(|msg| {
+                            let cmd = cmd.trim_matches('"');
+                            format!("Falha ao rodar o comando externo '{cmd}': {msg}")
+                        })?;
+
+                    return Ok(LiteralValue::StringValue(
+                        String::from_utf8_lossy(&output.stdout).to_string(),
+                    ));
+                };
+
+                let fun_val =
+                    LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+                        name: name.lexeme.clone(),
+                        arity: Arity::Exactly(0),
+                        fun: Rc::new(local_fn),
+                    }));
+                self.environment.define(name.lexeme.clone(), fun_val);
+            }
+            Stmt::ReturnStmt { keyword: _, value } => {
+                let eval_val;
+                if let Some(value) = value {
+                    eval_val = value.evaluate(self.environment.clone())?;
+                } else {
+                    eval_val = LiteralValue::Nil;
                 }
-            };
+                self.push_doc(&"fim");
+                return Ok(Flow::Return(eval_val));
+            }
+            Stmt::Yield { keyword, .. } => {
+                // Generator bodies are driven by GeneratorState, never by interpret
+                return Err(RuntimeError::new("'produz' só pode ser usado no corpo de uma função")
+                    .raised_at(Span::of(keyword)));
+            }
         }
 
-        Ok(())
+        Ok(Flow::Next)
+    }
+
+
+    /// Same as `self.doc.lines().count()`
+    fn doc_lines(&self) -> usize {
+        if self.doc.is_empty() || self.doc.ends_with('\n') {
+            self.doc_breaks
+        } else {
+            self.doc_breaks + 1
+        }
     }
 
+    fn push_doc(&mut self, text: &str) {
+        if self.doc.len() >= MAX_DOC_BYTES {
+            return;
+        }
+        self.doc_breaks += text.matches('\n').count();
+        self.doc.push_str(text);
+    }
 
     pub fn exporttofile(path: &str, value: String)
         {
@@ -267,18 +559,22 @@ impl Interpreter {
         if let Stmt::Function { name, params, body } = fn_stmt {
             let arity = params.len();
             let params: Vec<Token> = params.iter().map(|t| (*t).clone()).collect();
-            let body: Vec<Box<Stmt>> = body.iter().map(|b| (*b).clone()).collect();
+            let body: Vec<Stmt> = body.iter().map(|b| (*b).clone()).collect();
             let name_clone = name.lexeme.clone();
+            let is_generator = contains_yield(&body);
 
-            // TODO: Don't clone the whole environment, just the captured variables
+            // The function keeps its whole scope alive, and a local function is kept in that
+            // scope: the heap collects these cycles
             let parent_env = self.environment.clone();
 
             let callable_impl = LoxFunctionImpl {
                 name: name_clone,
                 arity,
                 parent_env,
-                params,
-                body,
+                params: Rc::new(params),
+                body: Rc::new(body),
+                is_generator,
+                class_name: None,
             };
 
             callable_impl
@@ -287,3 +583,15 @@ impl Interpreter {
         }
     }
 }
+
+/// `limpar valor`: clears the terminal and shows the value under the banner
+pub fn clear_screen(value: &LiteralValue) -> Result<(), RuntimeError> {
+    let saida = value
+        .to_string()
+        .replace("\\n", "\n")
+        .replace("\\t", "\t");
+    let screen = format!("{esc}[2J{esc}[1;1H\n[Fe Ferrugem vs 0.1]\nPortugol reescrito em Rust\n\n{}\n-------------------------------\n", saida, esc = 27 as char); //volta o cursor;
+    budget::output(screen.len())?;
+    print!("{screen}");
+    Ok(())
+}
@@ -1,64 +1,138 @@
+// Diagnostics travel by value through the parser and resolver, and are only built on errors
+#![allow(clippy::result_large_err)]
+
+mod budget;
+mod callstack;
+mod chunk;
+mod compiler;
+mod decimal;
+mod diagnostic;
 mod environment;
 mod expr;
+mod generator;
+mod heap;
 mod interpreter;
+mod math;
 mod parser;
 mod resolver;
 mod scanner;
 mod stmt;
+mod strings;
 mod tests;
+mod vm;
+use crate::diagnostic::{Diagnostic, Phase, RuntimeError};
 use crate::interpreter::*;
 use crate::parser::*;
 use crate::resolver::*;
 use crate::scanner::*;
 use std::env;
 use std::fs;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::process::exit;
+use std::time::Duration;
+
+/// How a program is run, from the command line flags
+#[derive(Default)]
+pub struct Options {
+    // Handed to the program through argumentos()
+    pub arguments: Vec<String>,
+    // Run with the bytecode VM instead of the tree-walking interpreter
+    pub vm: bool,
+    // Errors as JSON lines on stderr, for editors
+    pub json: bool,
+    // How many calls can be in progress at once, callstack::DEFAULT_MAX_DEPTH if not given
+    pub max_depth: Option<usize>,
+    // Steps, time and output the program may use before it is stopped
+    pub limits: budget::Limits,
+}
+
+// The tree-walker recurses natively for each call of the program, so it runs on a thread with
+// a stack far larger than callstack::DEFAULT_MAX_DEPTH calls need. The memory is only reserved.
+const STACK_SIZE: usize = 1 << 30;
+
+// Native stack a call of the program may take, with room for the expressions inside it:
+// measured up to about 450 KB in debug builds and 25 KB in release builds
+const STACK_PER_CALL: usize = if cfg!(debug_assertions) { 512 << 10 } else { 64 << 10 };
+
+// The deepest --profundidade the stack can hold
+const MAX_DEPTH: usize = STACK_SIZE / STACK_PER_CALL;
 
-pub fn run_file(path: &str) -> Result<(), String> {
+pub fn run_file(path: &str, options: &Options) -> Result<(), Vec<Diagnostic>> {
     // let mut interpreter = Interpreter::new();
     match fs::read_to_string(path) {
-        Err(msg) => return Err(msg.to_string()),
-        Ok(contents) => return run_string(&contents),
+        Err(msg) => {
+            let msg = format!("Não foi possível ler o arquivo: {msg}");
+            return Err(vec![Diagnostic::new(Phase::Leitura, "A001", msg).in_file(path)]);
+        }
+        Ok(contents) => {
+            return run_string(&contents, options)
+                .map_err(|errors| errors.into_iter().map(|error| error.in_file(path)).collect());
+        }
     }
 }
 
-pub fn run_string(contents: &str) -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+pub fn run_string(contents: &str, options: &Options) -> Result<(), Vec<Diagnostic>> {
+    let mut interpreter = Interpreter::new().with_limits(options.limits);
+    interpreter.define_arguments(&options.arguments);
 
-    run(&mut interpreter, contents)
+    run(&mut interpreter, contents, options)
 }
 
 #[test]
 fn test_run_string() {
     for exp in ["saida \"ola\";",] {
-        match run_string(exp) {
+        match run_string(exp, &Options::default()) {
                 Ok(_) => exit(0),
-                Err(msg) => {
-                    println!("ERRO:\n{msg}");
+                Err(errors) => {
+                    report(&errors, &Options::default());
                     exit(1);
                 }
             }
     }
 }
 
-fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String> {
+fn run(
+    interpreter: &mut Interpreter,
+    contents: &str,
+    options: &Options,
+) -> Result<(), Vec<Diagnostic>> {
 
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);//volta o cursor;
-    print!("[Fe] Ferrugem vs 0.1 🟠 \nPortugol sendo reescrito em Rust\n==================================\n");
+    // Only on a terminal, so the output can be piped to other programs
+    if io::stdout().is_terminal() {
+        print!("{esc}[2J{esc}[1;1H", esc = 27 as char);//volta o cursor;
+        print!("[Fe] Ferrugem vs 0.1 🟠 \nPortugol sendo reescrito em Rust\n==================================\n");
+    }
+
+    // Errors show the offending line of the program
+    let with_source = |errors: Vec<Diagnostic>| -> Vec<Diagnostic> {
+        errors.into_iter().map(|error| error.with_source(contents)).collect()
+    };
+    let runtime = |error: RuntimeError| vec![error.into_diagnostic().with_source(contents)];
 
     let mut scanner = Scanner::new(contents);
-    let tokens = scanner.scan_tokens()?;
+    let tokens = scanner.scan_tokens().map_err(with_source)?;
 
     let mut parser = Parser::new(tokens);
-    let stmts = parser.parse()?;
+    let stmts = parser.parse().map_err(with_source)?;
 
     let resolver = Resolver::new();
-    let locals = resolver.resolve(&stmts.iter().collect())?;
+    let locals = resolver
+        .resolve(&stmts.iter().collect())
+        .map_err(|error| with_source(vec![error]))?;
 
     interpreter.resolve(locals);
 
-    interpreter.interpret(stmts.iter().collect())?;
+    // Runtime errors say where they happened and which calls led to them
+    callstack::reset();
+    callstack::set_max_depth(options.max_depth.unwrap_or(callstack::DEFAULT_MAX_DEPTH));
+
+    // The VM shares the interpreter's globals, but doesn't write doc.md
+    if options.vm {
+        return vm::interpret(&stmts, interpreter.environment.clone(), interpreter.limits)
+            .map_err(runtime);
+    }
+
+    interpreter.run(&stmts).map_err(runtime)?;
     interpreter.doc();
     return Ok(());
 }
@@ -88,30 +162,122 @@ fn run_prompt() -> Result<(), String> {
         }
 
         println!("ECO: {}", buffer);
-        match run(&mut interpreter, &buffer) {
+        match run(&mut interpreter, &buffer, &Options::default()) {
             Ok(_) => (),
-            Err(msg) => println!("{}", msg),
+            Err(errors) => report(&errors, &Options::default()),
+        }
+    }
+}
+
+/// The value of a flag like `--passos=1000`, which must be a positive integer
+fn positive(flag: &str, name: &str) -> u64 {
+    match flag[name.len()..].parse::<u64>() {
+        Ok(value) if value > 0 => value,
+        _ => {
+            println!("🔴[Fe] O valor de {} precisa ser um número inteiro positivo: {flag}", name.trim_end_matches('='));
+            exit(64);
         }
     }
 }
 
+/// Exit code of a program that failed: a distinct one when it was stopped by its limits
+fn exit_code(errors: &[Diagnostic]) -> i32 {
+    if errors.iter().any(|error| error.code == budget::CODE) {
+        budget::EXIT_CODE
+    } else {
+        1
+    }
+}
+
+const USAGE: &str = "Uso: fe [opções] [arquivo.fe [argumentos...]] ou fe [opções] e \"codigo\" [argumentos...]
+Opções: --vm, --json, --profundidade=N (chamadas em andamento, 1000 se não informada),
+        --passos=N, --tempo=SEGUNDOS, --saida=BYTES (limites da execução)";
+
+/// Prints the errors of a program: underlined for people, or as JSON for editors
+fn report(errors: &[Diagnostic], options: &Options) {
+    if options.json {
+        for error in errors {
+            eprintln!("{}", error.to_json());
+        }
+        return;
+    }
+    println!("🔴[Fe] ERRO:");
+    for error in errors {
+        println!("{}", error.render());
+    }
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let program = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(start)
+        .expect("Não foi possível criar a linha de execução do programa");
+    // A panic was already reported by the thread
+    if program.join().is_err() {
+        exit(101);
+    }
+}
+
+fn start() {
+    let mut args: Vec<String> = env::args().collect();
+    let mut options = Options::default();
+
+    // Flags come before the program
+    while args.len() >= 2 && args[1].starts_with("--") {
+        match args[1].as_str() {
+            "--vm" => options.vm = true,
+            "--json" => options.json = true,
+            flag if flag.starts_with("--profundidade=") => {
+                let depth = positive(flag, "--profundidade=");
+                if depth > MAX_DEPTH as u64 {
+                    println!("🔴[Fe] O valor de --profundidade pode ser no máximo {MAX_DEPTH}: {flag}");
+                    exit(64);
+                }
+                options.max_depth = Some(depth as usize)
+            }
+            flag if flag.starts_with("--passos=") => {
+                options.limits.steps = Some(positive(flag, "--passos="))
+            }
+            flag if flag.starts_with("--tempo=") => {
+                match flag["--tempo=".len()..].parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                        options.limits.time = Some(Duration::from_secs_f64(seconds))
+                    }
+                    _ => {
+                        println!("🔴[Fe] O tempo precisa ser um número positivo de segundos: {flag}");
+                        exit(64);
+                    }
+                }
+            }
+            flag if flag.starts_with("--saida=") => {
+                options.limits.output = Some(positive(flag, "--saida=") as usize)
+            }
+            flag => {
+                println!("🔴[Fe] Opção desconhecida: {flag}");
+                println!("{USAGE}");
+                exit(64);
+            }
+        }
+        args.remove(1);
+    }
 
-    if args.len() == 2 {
-        match run_file(&args[1]) {
+    // Anything after the program is handed to it through argumentos()
+    if args.len() >= 3 && args[1] == "e" {
+        options.arguments = args[3..].to_vec();
+        match run_string(&args[2], &options) {
             Ok(_) => exit(0),
-            Err(msg) => {
-                println!("🔴[Fe] ERRO:\n{}", msg);
-                exit(1);
+            Err(errors) => {
+                report(&errors, &options);
+                exit(exit_code(&errors));
             }
         }
-    } else if args.len() == 3 && args[1] == "e" {
-        match run_string(&args[2]) {
+    } else if args.len() >= 2 && args[1] != "e" {
+        options.arguments = args[2..].to_vec();
+        match run_file(&args[1], &options) {
             Ok(_) => exit(0),
-            Err(msg) => {
-                println!("🔴[Fe] ERRO:\n{msg}");
-                exit(1);
+            Err(errors) => {
+                report(&errors, &options);
+                exit(exit_code(&errors));
             }
         }
     } else if args.len() == 1 {
@@ -124,6 +290,7 @@ fn main() {
         }
     } else {
         println!("🔴[Fe] Ferrugem Falhou");
+        println!("{USAGE}");
         exit(64);
     }
 }
@@ -0,0 +1,295 @@
+use crate::budget;
+use crate::decimal::{Decimal, Rounding};
+use crate::diagnostic::RuntimeError;
+use crate::expr::{Arity, CallableImpl, LiteralValue, NativeFunctionImpl};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type MathFn = fn(&str, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+
+fn expect_args(name: &str, args: &[LiteralValue], count: usize) -> Result<(), String> {
+    if args.len() != count {
+        return Err(format!(
+            "mat.{name} esperava {count} argumento(s) mas encontrou {}",
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+fn number_arg(name: &str, args: &[LiteralValue], i: usize) -> Result<f64, String> {
+    match &args[i] {
+        LiteralValue::Number(x) => Ok(*x),
+        LiteralValue::DecimalValue(x) => Ok(x.to_f64()),
+        other => Err(format!(
+            "mat.{name} esperava um número, mas recebeu um {}",
+            other.to_type()
+        )),
+    }
+}
+
+fn integer_arg(name: &str, args: &[LiteralValue], i: usize) -> Result<i64, String> {
+    match number_arg(name, args, i)? {
+        x if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => Ok(x as i64),
+        x => Err(format!("mat.{name} esperava um número inteiro, mas recebeu {x}")),
+    }
+}
+
+/// Functions of a single number
+fn unary(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    expect_args(name, args, 1)?;
+    let x = number_arg(name, args, 0)?;
+    let result = match name {
+        "raiz" => {
+            if x < 0.0 {
+                return Err(format!("mat.raiz não está definida para números negativos: {x}").into());
+            }
+            x.sqrt()
+        }
+        "abs" => x.abs(),
+        "piso" => x.floor(),
+        "teto" => x.ceil(),
+        "sen" => x.sin(),
+        "cos" => x.cos(),
+        "tan" => x.tan(),
+        "sen_graus" => x.to_radians().sin(),
+        "cos_graus" => x.to_radians().cos(),
+        "tan_graus" => {
+            if (x - 90.0).rem_euclid(180.0) == 0.0 {
+                return Err(format!("mat.tan_graus não está definida para {x} graus").into());
+            }
+            x.to_radians().tan()
+        }
+        _ => return Err(format!("O módulo mat não possui a função {name}").into()),
+    };
+    Ok(LiteralValue::Number(result))
+}
+
+fn power(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    expect_args(name, args, 2)?;
+    let base = number_arg(name, args, 0)?;
+    let exponent = number_arg(name, args, 1)?;
+    let result = base.powf(exponent);
+    if result.is_nan() {
+        return Err(format!("mat.potencia não está definida para {base} elevado a {exponent}").into());
+    }
+    Ok(LiteralValue::Number(result))
+}
+
+/// log(x) is the natural logarithm, log(x, base) any other
+fn log(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(format!("mat.log esperava 1 ou 2 argumentos mas encontrou {}", args.len()).into());
+    }
+    let x = number_arg(name, args, 0)?;
+    if x <= 0.0 {
+        return Err(format!("mat.log só está definido para números positivos: {x}").into());
+    }
+    if args.len() == 1 {
+        return Ok(LiteralValue::Number(x.ln()));
+    }
+
+    let base = number_arg(name, args, 1)?;
+    if base <= 0.0 || base == 1.0 {
+        return Err(format!("A base {base} não é válida para mat.log").into());
+    }
+    Ok(LiteralValue::Number(x.log(base)))
+}
+
+/// `arredondar(valor, casas)` and `mat.arredondar`, half-even unless a third argument names the
+/// mode. Computed in base 10, so 2.675 goes to 2.68.
+pub fn round_value(function: &str, args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(format!(
+            "A função {function} esperava 2 ou 3 argumentos mas encontrou {}",
+            args.len()
+        ));
+    }
+    let mode = match args.get(2) {
+        None => Rounding::HalfEven,
+        Some(LiteralValue::StringValue(name)) => Rounding::from_name(name)?,
+        Some(other) => {
+            return Err(format!("O modo de arredondamento deve ser um texto, não um {}", other.to_type()))
+        }
+    };
+    let places = match &args[1] {
+        LiteralValue::Number(x) if x.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(x) => *x as u32,
+        other => {
+            return Err(format!(
+                "{function} esperava um número de casas entre 0 e {}, mas recebeu {}",
+                u32::MAX,
+                other.to_string()
+            ))
+        }
+    };
+
+    match &args[0] {
+        LiteralValue::DecimalValue(x) => Ok(LiteralValue::DecimalValue(x.round(places, mode)?)),
+        LiteralValue::Number(x) => {
+            let rounded = Decimal::from_f64(*x)?.round(places, mode)?;
+            Ok(LiteralValue::Number(rounded.to_f64()))
+        }
+        other => Err(format!("Não é possível arredondar um {}", other.to_type())),
+    }
+}
+
+fn round(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    Ok(round_value(&format!("mat.{name}"), args)?)
+}
+
+/// min and max take any number of arguments
+fn extreme(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    if args.is_empty() {
+        return Err(format!("mat.{name} esperava ao menos um argumento").into());
+    }
+    let mut best = number_arg(name, args, 0)?;
+    let mut best_index = 0;
+    for i in 1..args.len() {
+        let x = number_arg(name, args, i)?;
+        if (name == "min" && x < best) || (name == "max" && x > best) {
+            best = x;
+            best_index = i;
+        }
+    }
+    Ok(args[best_index].clone())
+}
+
+fn gcd(mut a: i64, mut b: i64) -> i64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+
+fn integer_pair(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    expect_args(name, args, 2)?;
+    let a = integer_arg(name, args, 0)?;
+    let b = integer_arg(name, args, 1)?;
+    let result = match name {
+        "mdc" => gcd(a, b),
+        _ => {
+            if a == 0 || b == 0 {
+                0
+            } else {
+                (a / gcd(a, b))
+                    .checked_mul(b)
+                    .ok_or_else(|| format!("mat.mmc({a}, {b}) é grande demais para ser representado"))?
+                    .abs()
+            }
+        }
+    };
+    Ok(LiteralValue::Number(result as f64))
+}
+
+fn factorial(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    expect_args(name, args, 1)?;
+    let n = integer_arg(name, args, 0)?;
+    if n < 0 {
+        return Err(format!("mat.fatorial não está definido para números negativos: {n}").into());
+    }
+    // 171! no longer fits in a number
+    if n > 170 {
+        return Err(format!("mat.fatorial({n}) é grande demais para ser representado").into());
+    }
+    Ok(LiteralValue::Number((1..=n).fold(1.0, |acc, k| acc * k as f64)))
+}
+
+fn is_prime(name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    expect_args(name, args, 1)?;
+    let n = integer_arg(name, args, 0)?;
+    let mut prime = n >= 2;
+    let mut divisor = 2;
+    while prime && divisor <= n / divisor {
+        prime = n % divisor != 0;
+        divisor += 1;
+        // A large prime takes billions of divisions, so they count against the limits
+        if (divisor as u64).is_multiple_of(budget::WORK_PER_STEP) {
+            budget::charge(1)?;
+        }
+    }
+    Ok(LiteralValue::from_bool(prime))
+}
+
+const FUNCTIONS: [(&str, Arity, MathFn); 19] = [
+    ("raiz", Arity::Exactly(1), unary),
+    ("abs", Arity::Exactly(1), unary),
+    ("piso", Arity::Exactly(1), unary),
+    ("teto", Arity::Exactly(1), unary),
+    ("sen", Arity::Exactly(1), unary),
+    ("cos", Arity::Exactly(1), unary),
+    ("tan", Arity::Exactly(1), unary),
+    ("sen_graus", Arity::Exactly(1), unary),
+    ("cos_graus", Arity::Exactly(1), unary),
+    ("tan_graus", Arity::Exactly(1), unary),
+    ("potencia", Arity::Exactly(2), power),
+    ("log", Arity::Between(1, 2), log),
+    ("arredondar", Arity::Between(2, 3), round),
+    ("min", Arity::AtLeast(1), extreme),
+    ("max", Arity::AtLeast(1), extreme),
+    ("mdc", Arity::Exactly(2), integer_pair),
+    ("mmc", Arity::Exactly(2), integer_pair),
+    ("fatorial", Arity::Exactly(1), factorial),
+    ("eh_primo", Arity::Exactly(1), is_prime),
+];
+
+/// The `mat` module: `mat.raiz(2)`, `mat.pi`...
+pub fn math_module() -> LiteralValue {
+    let mut members = HashMap::new();
+    for (name, arity, fun) in FUNCTIONS {
+        members.insert(
+            name.to_string(),
+            LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+                name: format!("mat.{name}"),
+                arity,
+                fun: Rc::new(move |args: &Vec<LiteralValue>| fun(name, args)),
+            })),
+        );
+    }
+    members.insert("pi".to_string(), LiteralValue::Number(std::f64::consts::PI));
+    members.insert("e".to_string(), LiteralValue::Number(std::f64::consts::E));
+
+    LiteralValue::Namespace {
+        name: "mat".to_string(),
+        members: Rc::new(members),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(x: f64) -> LiteralValue {
+        LiteralValue::Number(x)
+    }
+
+    #[test]
+    fn reports_domain_errors() {
+        assert!(unary("raiz", &[n(-1.0)]).is_err());
+        assert!(log("log", &[n(0.0)]).is_err());
+        assert!(factorial("fatorial", &[n(-3.0)]).is_err());
+        assert!(factorial("fatorial", &[n(2.5)]).is_err());
+        assert!(unary("tan_graus", &[n(270.0)]).is_err());
+    }
+
+    #[test]
+    fn computes_integer_functions() {
+        assert_eq!(integer_pair("mdc", &[n(12.0), n(18.0)]).unwrap(), n(6.0));
+        assert_eq!(integer_pair("mmc", &[n(4.0), n(6.0)]).unwrap(), n(12.0));
+        assert_eq!(factorial("fatorial", &[n(5.0)]).unwrap(), n(120.0));
+        assert_eq!(is_prime("eh_primo", &[n(97.0)]).unwrap(), LiteralValue::True);
+        assert_eq!(is_prime("eh_primo", &[n(1.0)]).unwrap(), LiteralValue::False);
+    }
+
+    #[test]
+    fn rounds_and_picks_extremes() {
+        assert_eq!(round("arredondar", &[n(2.675), n(2.0)]).unwrap(), n(2.68));
+        assert_eq!(round("arredondar", &[n(-2.5), n(0.0)]).unwrap(), n(-2.0));
+        let half_up = LiteralValue::StringValue("meio_acima".to_string());
+        assert_eq!(round("arredondar", &[n(-2.5), n(0.0), half_up]).unwrap(), n(-3.0));
+        assert!(round("arredondar", &[n(2.5), n(4294967296.0)]).is_err());
+        assert!(round("arredondar", &[n(2.5), n(-1.0)]).is_err());
+        assert_eq!(extreme("max", &[n(3.0), n(9.0), n(-1.0)]).unwrap(), n(9.0));
+        assert_eq!(extreme("min", &[n(3.0), n(9.0), n(-1.0)]).unwrap(), n(-1.0));
+        assert!(extreme("min", &[]).is_err());
+    }
+}
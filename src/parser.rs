@@ -1,12 +1,19 @@
+use crate::diagnostic::{Diagnostic, Phase};
 use crate::expr::{Expr, Expr::*, LiteralValue};
 use crate::scanner::{Token, TokenType, TokenType::*};
 use crate::stmt::Stmt;
 
+// Deeper nesting of blocks, bodies or expressions is a syntax error, long before the parser
+// or the interpreter would run out of native stack
+pub const MAX_NESTING: usize = 256;
+
 // #[derive(Default)]
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     next_id: usize,
+    // Nested statements and expressions being parsed
+    depth: usize,
 }
 
 #[derive(Debug)]
@@ -21,7 +28,21 @@ impl Parser {
             tokens,
             current: 0,
             next_id: 0,
+            depth: 0,
+        }
+    }
+
+    /// Parses something nested inside what is being parsed
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+        if self.depth >= MAX_NESTING {
+            let msg = format!("O programa tem mais de {MAX_NESTING} níveis aninhados");
+            return Err(error("S008", msg, &self.peek())
+                .with_hint("Separe as partes mais internas em funções ou variáveis"));
         }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
     fn get_id(&mut self) -> usize {
@@ -31,7 +52,7 @@ impl Parser {
         id
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
         let mut stmts = vec![];
         let mut errs = vec![];
 
@@ -40,7 +61,12 @@ impl Parser {
             match stmt {
                 Ok(s) => stmts.push(s),
                 Err(msg) => {
+                    // Past too deep a nesting there is no sensible place to resume
+                    let too_deep = msg.code == "S008";
                     errs.push(msg);
+                    if too_deep {
+                        break;
+                    }
                     self.synchronize();
                 }
             }
@@ -49,23 +75,35 @@ impl Parser {
         if errs.len() == 0 {
             Ok(stmts)
         } else {
-            Err(errs.join("\n"))
+            Err(errs)
         }
     }
 
-    fn declaration(&mut self) -> Result<Stmt, String> {
+    fn declaration(&mut self) -> Result<Stmt, Diagnostic> {
         if self.match_token(Var) {
             self.var_declaration()
         } else if self.match_token(Fun) {
             self.function(FunctionKind::Function)
         } else if self.match_token(Class) {
-            self.class_declaration()
+            self.class_declaration(false)
+        } else if self.check_word("estrita") && self.check_next(Class) {
+            // estrita classe Ponto { campo x = 0; }
+            // Only a word before 'classe', so programs may still name a variable 'estrita'
+            self.advance();
+            self.advance();
+            self.class_declaration(true)
+        } else if self.check_word("contrato") && self.check_next(Identifier) {
+            self.advance();
+            self.contract_declaration()
+        } else if self.check_word("mixin") && self.check_next(Identifier) {
+            self.advance();
+            self.mixin_declaration()
         } else {
             self.statement()
         }
     }
 
-    fn class_declaration(&mut self) -> Result<Stmt, String> {
+    fn class_declaration(&mut self, strict: bool) -> Result<Stmt, Diagnostic> {
         let name = self.consume(Identifier, "Esperado o nome depois da palavra reservada 'classe' .")?;
         let superclass = if self.match_token(TokenType::Less) {
             self.consume(Identifier, "Esperada superclass depois do símbolo  '<'.")?;
@@ -77,12 +115,123 @@ impl Parser {
             None
         };
 
+        let mut mixins = vec![];
+        if self.match_word("usa") {
+            loop {
+                self.consume(Identifier, "Esperado o nome do mixin depois de 'usa'.")?;
+                mixins.push(Expr::Variable {
+                    id: self.get_id(),
+                    name: self.previous(),
+                });
+
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
+        }
+
+        let mut contracts = vec![];
+        if self.match_word("implementa") {
+            loop {
+                self.consume(Identifier, "Esperado o nome do contrato depois de 'implementa'.")?;
+                contracts.push(Expr::Variable {
+                    id: self.get_id(),
+                    name: self.previous(),
+                });
+
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
+        }
+
         self.consume(LeftBrace, "Esperado '{' antes do corpo da classe.")?;
 
         let mut methods = vec![];
+        let mut fields: Vec<(Token, Stmt)> = vec![];
+        let mut static_methods = vec![];
+        let mut static_fields = vec![];
+        let mut getters = vec![];
+        let mut setters = vec![];
         while !self.check(RightBrace) && !self.is_at_end() {
-            let method = self.function(FunctionKind::Method)?;
-            methods.push(Box::new(method));
+            if (self.check_word("obter") || self.check_word("definir")) && self.check_next(Identifier) {
+                // obter area() { ... }
+                // definir raio(v) { ... }
+                let keyword = self.advance();
+                let property = self.function(FunctionKind::Method)?;
+                let expected = if keyword.lexeme == "obter" { 0 } else { 1 };
+                if let Stmt::Function { name, params, .. } = &property {
+                    if params.len() != expected {
+                        let msg = format!(
+                            "'{} {}' deve receber {expected} parâmetro(s)",
+                            keyword.lexeme, name.lexeme
+                        );
+                        return Err(error("S006", msg, name));
+                    }
+                }
+                if keyword.lexeme == "obter" {
+                    getters.push(property);
+                } else {
+                    setters.push(property);
+                }
+            } else if self.check_word("campo") && self.check_next(Identifier) {
+                // campo nome = "";
+                // Kept as `_objeto.nome = ""`, run on each new object before 'ini'. Only a word
+                // before a name, so 'campo' is still a valid name, and `campo()` a method.
+                let keyword = self.advance();
+                let field = self.advance();
+                if fields.iter().any(|(declared, _)| declared.lexeme == field.lexeme) {
+                    let msg = format!("O campo '{}' já foi declarado nesta classe", field.lexeme);
+                    return Err(error("S007", msg, &field));
+                }
+                let value = if self.match_token(Equal) {
+                    self.expression()?
+                } else {
+                    Literal {
+                        id: self.get_id(),
+                        value: LiteralValue::Nil,
+                    }
+                };
+                self.consume(Semicolon, "Esperado ';' depois do campo")?;
+                let this = Expr::This {
+                    id: self.get_id(),
+                    keyword: Token {
+                        token_type: This,
+                        lexeme: "_objeto".to_string(),
+                        ..keyword
+                    },
+                };
+                let initializer = Expr::Set {
+                    id: self.get_id(),
+                    object: Box::new(this),
+                    name: field.clone(),
+                    value: Box::new(value),
+                };
+                fields.push((field, Stmt::Expression { expression: initializer }));
+            } else if self.check_word("estatico") && self.check_next(Identifier) {
+                // estatico contador = 0;
+                // estatico criar() { ... }
+                self.advance();
+                if self.check_next(LeftParen) {
+                    let method = self.function(FunctionKind::Method)?;
+                    static_methods.push(method);
+                } else {
+                    let field = self.consume(Identifier, "Esperado o nome do campo estático")?;
+                    let initializer = if self.match_token(Equal) {
+                        self.expression()?
+                    } else {
+                        Literal {
+                            id: self.get_id(),
+                            value: LiteralValue::Nil,
+                        }
+                    };
+                    self.consume(Semicolon, "Esperado ';' depois do campo estático")?;
+                    static_fields.push((field, initializer));
+                }
+            } else {
+                let method = self.function(FunctionKind::Method)?;
+                methods.push(method);
+            }
         }
 
         self.consume(RightBrace, "Esperado '}' depois do corpo da classe.")?;
@@ -90,11 +239,65 @@ impl Parser {
         Ok(Stmt::Class {
             name,
             methods,
+            fields,
+            strict,
             superclass,
+            static_methods,
+            static_fields,
+            getters,
+            setters,
+            contracts,
+            mixins,
         })
     }
 
-    fn function(&mut self, kind: FunctionKind) -> Result<Stmt, String> {
+    fn mixin_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        // mixin Nadador { nadar() { ... } }
+        let name = self.consume(Identifier, "Esperado o nome depois da palavra reservada 'mixin'.")?;
+        self.consume(LeftBrace, "Esperado '{' antes do corpo do mixin.")?;
+
+        let mut methods = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            let method = self.function(FunctionKind::Method)?;
+            methods.push(method);
+        }
+
+        self.consume(RightBrace, "Esperado '}' depois do corpo do mixin.")?;
+
+        Ok(Stmt::Mixin { name, methods })
+    }
+
+    fn contract_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        // contrato Forma { area(); escala(fator); }
+        let name = self.consume(Identifier, "Esperado o nome depois da palavra reservada 'contrato'.")?;
+        self.consume(LeftBrace, "Esperado '{' antes do corpo do contrato.")?;
+
+        let mut methods = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            let method = self.consume(Identifier, "Esperado o nome do método do contrato")?;
+            self.consume(LeftParen, "Esperado '(' depois do nome do método")?;
+            let mut arity = 0;
+            if !self.check(RightParen) {
+                loop {
+                    self.consume(Identifier, "Esperado o nome do parâmetro")?;
+                    arity += 1;
+
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+            }
+            self.consume(RightParen, "Esperado ')' depois dos parâmetros.")?;
+            self.consume(Semicolon, "Esperado ';' depois da assinatura do método")?;
+            methods.push((method, arity));
+        }
+
+        self.consume(RightBrace, "Esperado '}' depois do corpo do contrato.")?;
+
+        Ok(Stmt::Contract { name, methods })
+    }
+
+    fn function(&mut self, kind: FunctionKind) -> Result<Stmt, Diagnostic> {
         let name = self.consume(Identifier, &format!("Esperado o tipo {kind:?} "))?;
 
         if self.match_token(Gets) {
@@ -113,10 +316,8 @@ impl Parser {
         if !self.check(RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Linha {location}: Estourou o limite de 255 argumentos"
-                    ));
+                    let msg = "Estourou o limite de 255 argumentos";
+                    return Err(error("S004", msg, &self.peek()));
                 }
 
                 let param = self.consume(Identifier, "Esperado o nome do parâmetro")?;
@@ -142,11 +343,17 @@ impl Parser {
         })
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, String> {
+    fn var_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        if self.match_token(LeftParen) {
+            return self.var_tuple_declaration();
+        }
+
         let token = self.consume(Identifier, "Esperado o nome da variável")?;
 
         let initializer;
-        if self.match_token(Equal) {
+        if self.match_token(Colon) {
+            initializer = self.array_declaration(token.clone())?;
+        } else if self.match_token(Equal) {
             initializer = self.expression()?;
         } else {
             initializer = Literal {
@@ -163,7 +370,67 @@ impl Parser {
         })
     }
 
-    fn statement(&mut self) -> Result<Stmt, String> {
+    fn var_tuple_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        // var (quociente, resto) = dividir(7, 2);
+        let mut names = vec![];
+        loop {
+            let name = self.consume(Identifier, "Esperado o nome da variável")?;
+            names.push(name);
+
+            if !self.match_token(Comma) {
+                break;
+            }
+        }
+        self.consume(RightParen, "Esperado ')' depois das variáveis")?;
+        self.consume(Equal, "Esperado '=' na desestruturação")?;
+        let initializer = self.expression()?;
+        self.consume(Semicolon, "Esperado ';' depois da declaração da variável")?;
+
+        Ok(Stmt::VarTuple { names, initializer })
+    }
+
+    fn array_declaration(&mut self, name: Token) -> Result<Expr, Diagnostic> {
+        // var notas: vetor[1..10] de real;
+        // var m: matriz[1..3, 1..3] de inteiro;
+        if !self.match_word("vetor") && !self.match_word("matriz") {
+            let msg = "Esperado 'vetor' ou 'matriz' depois de ':'";
+            return Err(error("S001", msg, &self.peek()));
+        }
+        self.consume(LeftBracket, "Esperado '[' antes dos limites")?;
+
+        let mut bounds = vec![];
+        loop {
+            let lower = self.term()?;
+            self.consume(DotDot, "Esperado '..' entre os limites")?;
+            let upper = self.term()?;
+            bounds.push((lower, upper));
+
+            if !self.match_token(Comma) {
+                break;
+            }
+        }
+        self.consume(RightBracket, "Esperado ']' depois dos limites")?;
+        self.consume_word("de", "Esperado 'de' antes do tipo dos elementos")?;
+
+        let element_type = self.consume(Identifier, "Esperado o tipo dos elementos")?;
+        match element_type.lexeme.as_str() {
+            "inteiro" | "real" | "texto" | "logico" => (),
+            other => {
+                let msg = format!("Tipo '{other}' desconhecido");
+                return Err(error("S005", msg, &element_type)
+                    .with_hint("Use inteiro, real, texto ou logico"));
+            }
+        }
+
+        Ok(Expr::Array {
+            id: self.get_id(),
+            name,
+            bounds,
+            element_type,
+        })
+    }
+
+    fn statement(&mut self) -> Result<Stmt, Diagnostic> {
         if self.match_token(Print) {
             self.print_statement()
         }
@@ -179,12 +446,16 @@ impl Parser {
             self.for_statement()
         } else if self.match_token(Return) {
             self.return_statement()
+        } else if self.check_word("produz") && self.starts_operand(1) {
+            // produz valor; but `produz = 1;` still assigns to a variable named produz
+            self.advance();
+            self.yield_statement()
         } else {
             self.expression_statement()
         }
     }
 
-    fn return_statement(&mut self) -> Result<Stmt, String> {
+    fn return_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let keyword = self.previous();
         let value;
         if !self.check(Semicolon) {
@@ -198,7 +469,19 @@ impl Parser {
         Ok(Stmt::ReturnStmt { keyword, value })
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, String> {
+    fn yield_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(Semicolon, "Esperado ';' depois do valor produzido;")?;
+
+        Ok(Stmt::Yield { keyword, value })
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        if self.check(Identifier) {
+            return self.for_range_statement();
+        }
+
         // for v
         //       ( SMTH ; SMTH ; SMTH )
         self.consume(LeftParen, "Esperado '(' depois 'for'.")?;
@@ -234,14 +517,11 @@ impl Parser {
         }
         self.consume(RightParen, "Esperado ')' depois da cláusula para.")?;
 
-        let mut body = self.statement()?;
+        let mut body = self.nested(Self::statement)?;
 
         if let Some(incr) = increment {
             body = Stmt::Block {
-                statements: vec![
-                    Box::new(body),
-                    Box::new(Stmt::Expression { expression: incr }),
-                ],
+                statements: vec![body, Stmt::Expression { expression: incr }],
             };
         }
 
@@ -262,18 +542,59 @@ impl Parser {
 
         if let Some(init) = initializer {
             body = Stmt::Block {
-                statements: vec![Box::new(init), Box::new(body)],
+                statements: vec![init, body],
             };
         }
 
         Ok(body)
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, String> {
+    fn for_range_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        // para i de 1 ate 10 passo 2 { ... }
+        // para i em 0..10 { ... }
+        let variable = self.consume(Identifier, "Esperado o nome da variável do laço")?;
+
+        let range;
+        if self.match_word("de") {
+            let operator = self.previous();
+            let start = self.expression()?;
+            self.consume_word("ate", "Esperado 'ate' depois do valor inicial")?;
+            let end = self.expression()?;
+            let step = if self.match_word("passo") {
+                Some(Box::new(self.expression()?))
+            } else {
+                None
+            };
+
+            range = Expr::Range {
+                id: self.get_id(),
+                start: Box::new(start),
+                operator,
+                end: Box::new(end),
+                step,
+                inclusive: true,
+            };
+        } else if self.match_word("em") {
+            range = self.expression()?;
+        } else {
+            let msg = "Esperado 'de' ou 'em' depois da variável do laço";
+            return Err(error("S001", msg, &self.peek()));
+        }
+
+        let body = self.nested(Self::statement)?;
+
+        Ok(Stmt::ForRange {
+            variable,
+            range,
+            body: Box::new(body),
+        })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(LeftParen, "Esperado '(' depois de 'enquanto'")?;
         let condition = self.expression()?;
         self.consume(RightParen, "Esperado ')' depois da condição.")?;
-        let body = self.statement()?;
+        let body = self.nested(Self::statement)?;
 
         Ok(Stmt::WhileStmt {
             condition,
@@ -281,14 +602,14 @@ impl Parser {
         })
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, String> {
+    fn if_statement(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(LeftParen, "Esperado '(' depois do bloco 'se'")?;
         let predicate = self.expression()?;
         self.consume(RightParen, "Esperado ')' depois do predicado se")?;
 
-        let then = Box::new(self.statement()?);
+        let then = Box::new(self.nested(Self::statement)?);
         let els = if self.match_token(Else) {
-            let stm = self.statement()?;
+            let stm = self.nested(Self::statement)?;
             Some(Box::new(stm))
         } else {
             None
@@ -301,19 +622,19 @@ impl Parser {
         })
     }
 
-    fn block_statement(&mut self) -> Result<Stmt, String> {
+    fn block_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let mut statements = vec![];
 
         while !self.check(RightBrace) && !self.is_at_end() {
-            let decl = self.declaration()?;
-            statements.push(Box::new(decl));
+            let decl = self.nested(Self::declaration)?;
+            statements.push(decl);
         }
 
         self.consume(RightBrace, "Esperado '}' depois do bloco")?;
         Ok(Stmt::Block { statements })
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, String> {
+    fn print_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let value = self.expression()?;
         match self.consume(Semicolon, "Esperado ';' depois do valor.") {
             _=> () 
@@ -321,7 +642,7 @@ impl Parser {
         Ok(Stmt::Print { expression: value })
     }
 
-    fn limpa_tela(&mut self) -> Result<Stmt, String> {
+    fn limpa_tela(&mut self) -> Result<Stmt, Diagnostic> {
         let value = self.expression()?;
         match self.consume(Semicolon, "Esperado ';' depois do valor.") {
             _=> () 
@@ -329,7 +650,7 @@ impl Parser {
         Ok(Stmt::Limpar { expression: value })
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt, String> {
+    fn expression_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let expr = self.expression()?;
         match self.consume(Semicolon, "Esperado ';' depois da expressão."){
             _ => ()
@@ -337,20 +658,18 @@ impl Parser {
         Ok(Stmt::Expression { expression: expr })
     }
 
-    fn expression(&mut self) -> Result<Expr, String> {
-        self.assignment()
+    fn expression(&mut self) -> Result<Expr, Diagnostic> {
+        self.nested(Self::assignment)
     }
 
-    fn function_expression(&mut self) -> Result<Expr, String> {
+    fn function_expression(&mut self) -> Result<Expr, Diagnostic> {
         let paren = self.consume(LeftParen, "Esperado '(' depois da função anônima")?;
         let mut parameters = vec![];
         if !self.check(RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Estourou o limite de 255 argumentos"
-                    ));
+                    let msg = "Estourou o limite de 255 argumentos";
+                    return Err(error("S004", msg, &self.peek()));
                 }
 
                 let param = self.consume(Identifier, "Esperado o parâmetro nome")?;
@@ -384,11 +703,12 @@ impl Parser {
         })
     }
 
-    fn assignment(&mut self) -> Result<Expr, String> {
+    fn assignment(&mut self) -> Result<Expr, Diagnostic> {
         // a = 2; NOT var a = 2;
         let expr = self.pipe()?; // a |> f = 2;
 
         if self.match_token(Equal) {
+            let equals = self.previous();
             let value = self.expression()?;
 
             match expr {
@@ -407,14 +727,42 @@ impl Parser {
                     name,
                     value: Box::new(value),
                 }),
-                _ => Err("Destino inválido.".to_string()),
+                Index {
+                    id: _,
+                    object,
+                    bracket,
+                    indices,
+                } => Ok(SetIndex {
+                    id: self.get_id(),
+                    object,
+                    bracket,
+                    indices,
+                    value: Box::new(value),
+                }),
+                Tuple { id: _, elements } => {
+                    // (a, b) = (b, a);
+                    for target in elements.iter() {
+                        if let Variable { .. } = target {
+                            continue;
+                        }
+                        let msg = "Destino inválido na desestruturação.";
+                        return Err(error("S003", msg, &equals)
+                            .with_hint("Só variáveis podem receber os valores de uma tupla"));
+                    }
+                    Ok(AssignTuple {
+                        id: self.get_id(),
+                        targets: elements,
+                        value: Box::new(value),
+                    })
+                }
+                _ => Err(error("S003", "Destino inválido.", &equals)),
             }
         } else {
             Ok(expr)
         }
     }
 
-    fn pipe(&mut self) -> Result<Expr, String> {
+    fn pipe(&mut self) -> Result<Expr, Diagnostic> {
         // expr |> f
         // expr |> f1 |> f2
         // expr |> (f1 |> f2)
@@ -438,7 +786,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn or(&mut self) -> Result<Expr, String> {
+    fn or(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.and()?;
 
         while self.match_token(Or) {
@@ -456,7 +804,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, String> {
+    fn and(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.equality()?;
 
         while self.match_token(And) {
@@ -473,7 +821,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<Expr, String> {
+    fn equality(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.comparison()?;
         while self.match_tokens(&[BangEqual, EqualEqual]) {
             let operator = self.previous();
@@ -489,12 +837,61 @@ impl Parser {
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, String> {
-        let mut expr = self.term()?;
+    fn comparison(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.range()?;
 
         while self.match_tokens(&[Greater, GreaterEqual, Less, LessEqual]) {
             let op = self.previous();
-            let rhs = self.term()?;
+            let rhs = self.range()?;
+            expr = Binary {
+                id: self.get_id(),
+                left: Box::from(expr),
+                operator: op,
+                right: Box::from(rhs),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn range(&mut self) -> Result<Expr, Diagnostic> {
+        // 1..10, 1..=10, 10..0 passo -2
+        let expr = self.bit_or()?;
+
+        if self.match_tokens(&[DotDot, DotDotEqual]) {
+            let operator = self.previous();
+            let end = self.bit_or()?;
+            let step = if self.match_word("passo") {
+                Some(Box::new(self.bit_or()?))
+            } else {
+                None
+            };
+
+            return Ok(Range {
+                id: self.get_id(),
+                start: Box::new(expr),
+                inclusive: operator.token_type == DotDotEqual,
+                operator,
+                end: Box::new(end),
+                step,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary_level(&[Bar], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, Diagnostic> {
+        // 'xor' is an operator only after an operand, so it may still name a variable
+        let mut expr = self.bit_and()?;
+
+        while self.check_word("xor") {
+            self.tokens[self.current].token_type = Xor;
+            let op = self.advance();
+            let rhs = self.bit_and()?;
             expr = Binary {
                 id: self.get_id(),
                 left: Box::from(expr),
@@ -506,7 +903,37 @@ impl Parser {
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, String> {
+    fn bit_and(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary_level(&[Ampersand], Self::shift)
+    }
+
+    fn shift(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary_level(&[LessLess, GreaterGreater], Self::term)
+    }
+
+    /// Left-associative binary operators of one precedence level
+    fn binary_level(
+        &mut self,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr, Diagnostic>,
+    ) -> Result<Expr, Diagnostic> {
+        let mut expr = operand(self)?;
+
+        while self.match_tokens(operators) {
+            let op = self.previous();
+            let rhs = operand(self)?;
+            expr = Binary {
+                id: self.get_id(),
+                left: Box::from(expr),
+                operator: op,
+                right: Box::from(rhs),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.factor()?;
 
         while self.match_tokens(&[Minus, Plus]) {
@@ -523,7 +950,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, String> {
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.unary()?;
         while self.match_tokens(&[Slash, Star]) {
             let op = self.previous();
@@ -539,10 +966,14 @@ impl Parser {
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, String> {
-        if self.match_tokens(&[Bang, Minus]) {
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        // 'compl' is an operator only before an operand, so it may still name a variable
+        if self.check_word("compl") && self.starts_operand(1) {
+            self.tokens[self.current].token_type = Compl;
+        }
+        if self.match_tokens(&[Bang, Minus, Compl]) {
             let op = self.previous();
-            let rhs = self.unary()?;
+            let rhs = self.nested(Self::unary)?;
             Ok(Unary {
                 id: self.get_id(),
                 operator: op,
@@ -553,19 +984,39 @@ impl Parser {
         }
     }
 
-    fn call(&mut self) -> Result<Expr, String> {
+    fn call(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.primary()?;
 
         loop {
             if self.match_token(LeftParen) {
                 expr = self.finish_call(expr)?;
             } else if self.match_token(Dot) {
+                // Keywords are valid member names, as in `mat.e`
+                if self.peek().lexeme.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                    self.tokens[self.current].token_type = Identifier;
+                }
                 let name = self.consume(Identifier, "Esperado token depois do ponto")?;
                 expr = Get {
                     id: self.get_id(),
                     object: Box::new(expr),
                     name,
                 };
+            } else if self.match_token(LeftBracket) {
+                let bracket = self.previous();
+                let mut indices = vec![];
+                loop {
+                    indices.push(self.expression()?);
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightBracket, "Esperado ']' depois dos índices")?;
+                expr = Index {
+                    id: self.get_id(),
+                    object: Box::new(expr),
+                    bracket,
+                    indices,
+                };
             } else {
                 break;
             }
@@ -574,7 +1025,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, String> {
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, Diagnostic> {
         let mut arguments = vec![];
 
         if !self.check(RightParen) {
@@ -582,10 +1033,8 @@ impl Parser {
                 let arg = self.expression()?;
                 arguments.push(arg);
                 if arguments.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Estourou o limite de 255 argumentos [571]"
-                    ));
+                    let msg = "Estourou o limite de 255 argumentos";
+                    return Err(error("S004", msg, &self.peek()));
                 }
 
                 if !self.match_token(Comma) {
@@ -603,20 +1052,36 @@ impl Parser {
         })
     }
 
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
         let token = self.peek();
         let result;
         match token.token_type {
             LeftParen => {
                 self.advance();
                 let expr = self.expression()?;
-                self.consume(RightParen, "Esperado ')'")?;
-                result = Grouping {
-                    id: self.get_id(),
-                    expression: Box::from(expr),
-                };
+                if self.match_token(Comma) {
+                    // (a, b, c)
+                    let mut elements = vec![expr];
+                    loop {
+                        elements.push(self.expression()?);
+                        if !self.match_token(Comma) {
+                            break;
+                        }
+                    }
+                    self.consume(RightParen, "Esperado ')' depois dos elementos da tupla")?;
+                    result = Tuple {
+                        id: self.get_id(),
+                        elements,
+                    };
+                } else {
+                    self.consume(RightParen, "Esperado ')'")?;
+                    result = Grouping {
+                        id: self.get_id(),
+                        expression: Box::from(expr),
+                    };
+                }
             }
-            False | True | Nil | Number | StringLit => {
+            False | True | Nil | Number | DecimalLit | StringLit => {
                 self.advance();
                 result = Literal {
                     id: self.get_id(),
@@ -653,20 +1118,20 @@ impl Parser {
                 self.advance();
                 result = self.function_expression()?;
             }
-            _ => return Err("Uma expressão era esperada".to_string()),
+            _ => return Err(error("S002", "Uma expressão era esperada", &token)),
         }
 
         Ok(result)
     }
 
-    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, String> {
+    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, Diagnostic> {
         let token = self.peek();
         if token.token_type == token_type {
             self.advance();
             let token = self.previous();
             Ok(token)
         } else {
-            Err(format!("Line {}: {}", token.line_number, msg))
+            Err(error("S001", msg, &token))
         }
     }
 
@@ -674,6 +1139,59 @@ impl Parser {
         self.peek().token_type == typ
     }
 
+    fn check_next(&mut self, typ: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == typ,
+            None => false,
+        }
+    }
+
+    /// Whether the next token is the name `word`, for words that are only keywords in context
+    fn check_word(&mut self, word: &str) -> bool {
+        self.check(Identifier) && self.peek().lexeme == word
+    }
+
+    fn match_word(&mut self, word: &str) -> bool {
+        if self.check_word(word) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_word(&mut self, word: &str, msg: &str) -> Result<Token, Diagnostic> {
+        if self.check_word(word) {
+            Ok(self.advance())
+        } else {
+            Err(error("S001", msg, &self.peek()))
+        }
+    }
+
+    /// Whether the token `offset` places ahead can begin a value, which tells `produz x` and
+    /// `compl x` apart from uses of variables with those names
+    fn starts_operand(&self, offset: usize) -> bool {
+        match self.tokens.get(self.current + offset) {
+            Some(token) => matches!(
+                token.token_type,
+                LeftParen
+                    | Minus
+                    | Bang
+                    | False
+                    | True
+                    | Nil
+                    | Number
+                    | DecimalLit
+                    | StringLit
+                    | Identifier
+                    | This
+                    | Super
+                    | Fun
+            ),
+            None => false,
+        }
+    }
+
     fn match_token(&mut self, typ: TokenType) -> bool {
         if self.is_at_end() {
             false
@@ -735,6 +1253,11 @@ impl Parser {
     }
 }
 
+/// A syntax error pointing at `token`
+fn error(code: &'static str, msg: impl Into<String>, token: &Token) -> Diagnostic {
+    Diagnostic::new(Phase::Sintatica, code, msg).at_token(token)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -747,30 +1270,35 @@ mod tests {
             lexeme: "1".to_string(),
             literal: Some(FValue(1.0)),
             line_number: 0,
+            column: 0,
         };
         let plus = Token {
             token_type: Plus,
             lexeme: "+".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let two = Token {
             token_type: Number,
             lexeme: "2".to_string(),
             literal: Some(FValue(2.0)),
             line_number: 0,
+            column: 0,
         };
         let semicol = Token {
             token_type: Semicolon,
             lexeme: ";".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let eof = Token {
             token_type: Eof,
             lexeme: "".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
 
         let tokens = vec![one, plus, two, semicol, eof];
@@ -805,4 +1333,54 @@ mod tests {
 
         assert_eq!(string_expr, "(== 1 (group (+ 2 2)))");
     }
+
+    #[test]
+    fn campo_and_estrita_are_keywords_only_in_context() {
+        let parse = |source: &str| {
+            let tokens = Scanner::new(source).scan_tokens().unwrap();
+            Parser::new(tokens).parse()
+        };
+        assert!(parse("var campo = 1; var estrita = campo;").is_ok());
+        assert!(parse("estrita classe A { campo x = 1; campo() { retorna 2; } }").is_ok());
+
+        let errors = parse("classe A { campo x; campo x = 2; }").unwrap_err();
+        assert_eq!(errors[0].code, "S007");
+    }
+
+    #[test]
+    fn words_added_by_features_are_keywords_only_in_context() {
+        let parse = |source: &str| {
+            let tokens = Scanner::new(source).scan_tokens().unwrap();
+            Parser::new(tokens).parse()
+        };
+        assert!(parse("var de = 1; var em = de; var passo = em + 1; saida passo;").is_ok());
+        assert!(parse("var xor = 1; var compl = 2; saida xor xor compl compl;").is_ok());
+        assert!(parse("var produz = 1; produz = produz + 1; fun mixin(contrato) {}").is_ok());
+        assert!(parse("para i de 1 ate 10 passo 2 { } para x em 0..3 passo 1 { }").is_ok());
+        assert!(parse("fun g() { produz 1; produz (2); }").is_ok());
+
+        let stmts = parse("classe A usa B implementa C { obter x() {} estatico() {} }").unwrap();
+        let Stmt::Class { getters, methods, mixins, contracts, .. } = &stmts[0] else {
+            panic!("Esperada uma classe");
+        };
+        assert_eq!((getters.len(), methods.len(), mixins.len(), contracts.len()), (1, 1, 1, 1));
+    }
+
+    #[test]
+    fn rejects_nesting_deeper_than_the_limit() {
+        let parse = |depth: usize| {
+            let source = format!("saida {}1{};", "(".repeat(depth), ")".repeat(depth));
+            let tokens = Scanner::new(&source).scan_tokens().unwrap();
+            Parser::new(tokens).parse()
+        };
+        // A debug build needs more than the 2 MB of a test thread for the deepest nesting
+        let run = std::thread::Builder::new().stack_size(64 << 20).spawn(move || {
+            assert!(parse(MAX_NESTING - 1).is_ok());
+
+            let errors = parse(MAX_NESTING).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].code, "S008");
+        });
+        run.unwrap().join().unwrap();
+    }
 }
@@ -1,3 +1,4 @@
+use crate::diagnostic::{Diagnostic, Phase};
 use crate::expr::Expr;
 use crate::scanner::Token;
 use crate::stmt::Stmt;
@@ -8,13 +9,25 @@ enum FunctionType {
     None,
     Function,
     Method,
+    StaticMethod,
 }
 
+/// Where a local variable lives: how many scopes up from the one using it, and its
+/// position among the variables of that scope, in the order they are declared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalSlot {
+    pub distance: usize,
+    pub index: usize,
+}
+
+/// A scope being resolved: each name with its slot and whether it is already defined
+type Scope = HashMap<String, (usize, bool)>;
+
 #[allow(dead_code)]
 pub struct Resolver {
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<Scope>,
     current_function: FunctionType,
-    locals: HashMap<usize, usize>,
+    locals: HashMap<usize, LocalSlot>,
 }
 
 impl Resolver {
@@ -26,17 +39,34 @@ impl Resolver {
         }
     }
 
-    fn resolve_internal(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_internal(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
         match stmt {
             Stmt::Block { statements: _ } => self.resolve_block(stmt)?,
             Stmt::Var {
                 name: _,
                 initializer: _,
             } => self.resolve_var(stmt)?,
+            Stmt::VarTuple { names, initializer } => {
+                for name in names {
+                    self.declare(name)?;
+                }
+                self.resolve_expr(initializer)?;
+                for name in names {
+                    self.define(name);
+                }
+            }
             Stmt::Class {
                 name,
                 methods,
+                fields,
+                strict: _,
                 superclass,
+                static_methods,
+                static_fields,
+                getters,
+                setters,
+                contracts,
+                mixins,
             } => {
                 // Resolve superclass, if present
                 if let Some(super_expr) = superclass {
@@ -46,37 +76,66 @@ impl Resolver {
                     } = super_expr
                     {
                         if super_name.lexeme == name.lexeme {
-                            return Err("Não é possível fazer herança dele mesmo. Referência circular não é permitida".to_string());
+                            let msg = "Não é possível fazer herança dele mesmo. Referência circular não é permitida";
+                            return Err(error("R001", msg, super_name));
                         }
                     }
 
                     self.resolve_expr(super_expr)?;
-                    self.begin_scope();
-                    self.scopes
-                        .last_mut()
-                        .unwrap()
-                        .insert("super".to_string(), true);
+                }
+
+                for mixin in mixins.iter().chain(contracts.iter()) {
+                    self.resolve_expr(mixin)?;
                 }
 
                 // Resolving class
                 self.declare(name)?;
                 self.define(name);
 
+                // The interpreter always encloses the class body, with 'super' when present
+                self.begin_scope();
+                if superclass.is_some() {
+                    self.declare_implicit("super");
+                }
+
+                for (_, initializer) in static_fields {
+                    self.resolve_expr(initializer)?;
+                }
+
                 // Resolving methods
                 self.begin_scope();
-                self.scopes
-                    .last_mut()
-                    .unwrap()
-                    .insert("_objeto".to_string(), true);
+                self.declare_implicit("_objeto");
+                // Field initializers run like the body of a method without parameters
+                if !fields.is_empty() {
+                    self.resolve_function_helper(
+                        &vec![],
+                        &fields.iter().map(|(_, initializer)| initializer).collect(),
+                        FunctionType::Method,
+                    )?;
+                }
                 for method in methods {
                     let declaration = FunctionType::Method;
                     self.resolve_function(method, declaration)?;
                 }
+                // A getter and a setter may share the property name, so they are not declared
+                for property in getters.iter().chain(setters.iter()) {
+                    if let Stmt::Function { name: _, params, body } = property {
+                        self.resolve_function_helper(
+                            params,
+                            &body.iter().collect(),
+                            FunctionType::Method,
+                        )?;
+                    }
+                }
                 self.end_scope();
 
-                if superclass.is_some() {
-                    self.end_scope();
+                // Static methods are not bound to an instance, so they live outside the
+                // '_objeto' scope
+                for method in static_methods {
+                    self.resolve_function(method, FunctionType::StaticMethod)?;
                 }
+
+                self.end_scope();
             }
             Stmt::Function {
                 name: _,
@@ -84,6 +143,24 @@ impl Resolver {
                 body: _,
             } => self.resolve_function(stmt, FunctionType::Function)?,
             Stmt::CmdFunction { name: _, cmd: _ } => self.resolve_var(stmt)?,
+            Stmt::Contract { name, methods: _ } => {
+                self.declare(name)?;
+                self.define(name);
+            }
+            Stmt::Mixin { name, methods } => {
+                self.declare(name)?;
+                self.define(name);
+
+                // Same layout as a class without superclass: the body scope, then '_objeto'
+                self.begin_scope();
+                self.begin_scope();
+                self.declare_implicit("_objeto");
+                for method in methods {
+                    self.resolve_function(method, FunctionType::Method)?;
+                }
+                self.end_scope();
+                self.end_scope();
+            }
             Stmt::Expression { expression } => self.resolve_expr(expression)?,
             Stmt::IfStmt {
                 predicate: _,
@@ -92,24 +169,45 @@ impl Resolver {
             } => self.resolve_if_stmt(stmt)?,
             Stmt::Print { expression } => self.resolve_expr(expression)?,
             Stmt::Limpar { expression } => self.resolve_expr(expression)?,
-            Stmt::ReturnStmt { keyword: _, value } => {
+            Stmt::ReturnStmt { keyword, value } => {
                 if self.current_function == FunctionType::None {
-                    return Err("Só é possível retornar um valor de dentro de uma função".to_string());
+                    let msg = "Só é possível retornar um valor de dentro de uma função";
+                    return Err(error("R002", msg, keyword));
                 }
 
                 if let Some(value) = value {
                     self.resolve_expr(value)?;
                 }
             }
+            Stmt::Yield { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    let msg = "Só é possível produzir um valor de dentro de uma função";
+                    return Err(error("R002", msg, keyword));
+                }
+
+                self.resolve_expr(value)?;
+            }
             Stmt::WhileStmt { condition, body } => {
                 self.resolve_expr(condition)?;
                 self.resolve_internal(body.as_ref())?;
             }
+            Stmt::ForRange {
+                variable,
+                range,
+                body,
+            } => {
+                self.resolve_expr(range)?;
+                self.begin_scope();
+                self.declare(variable)?;
+                self.define(variable);
+                self.resolve_internal(body.as_ref())?;
+                self.end_scope();
+            }
         }
         Ok(())
     }
 
-    fn resolve_many(&mut self, stmts: &Vec<&Stmt>) -> Result<(), String> {
+    fn resolve_many(&mut self, stmts: &Vec<&Stmt>) -> Result<(), Diagnostic> {
         for stmt in stmts {
             self.resolve_internal(stmt)?;
         }
@@ -117,16 +215,16 @@ impl Resolver {
         Ok(())
     }
 
-    pub fn resolve(mut self, stmts: &Vec<&Stmt>) -> Result<HashMap<usize, usize>, String> {
+    pub fn resolve(mut self, stmts: &Vec<&Stmt>) -> Result<HashMap<usize, LocalSlot>, Diagnostic> {
         self.resolve_many(stmts)?;
         Ok(self.locals)
     }
 
-    fn resolve_block(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_block(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
         match stmt {
             Stmt::Block { statements } => {
                 self.begin_scope();
-                self.resolve_many(&statements.iter().map(|b| b.as_ref()).collect())?;
+                self.resolve_many(&statements.iter().collect())?;
                 self.end_scope();
             }
             _ => panic!("Tipo incorreto"),
@@ -135,7 +233,7 @@ impl Resolver {
         Ok(())
     }
 
-    fn resolve_var(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_var(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
         if let Stmt::Var { name, initializer } = stmt {
             self.declare(name)?;
             self.resolve_expr(initializer)?;
@@ -150,14 +248,14 @@ impl Resolver {
         Ok(())
     }
 
-    fn resolve_function(&mut self, stmt: &Stmt, fn_type: FunctionType) -> Result<(), String> {
+    fn resolve_function(&mut self, stmt: &Stmt, fn_type: FunctionType) -> Result<(), Diagnostic> {
         if let Stmt::Function { name, params, body } = stmt {
             self.declare(name)?;
             self.define(name);
 
             self.resolve_function_helper(
                 params,
-                &body.iter().map(|b| b.as_ref()).collect(),
+                &body.iter().collect(),
                 fn_type,
             )
         } else {
@@ -165,7 +263,7 @@ impl Resolver {
         }
     }
 
-    fn resolve_if_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn resolve_if_stmt(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
         if let Stmt::IfStmt {
             predicate,
             then,
@@ -189,7 +287,7 @@ impl Resolver {
         params: &Vec<Token>,
         body: &Vec<&Stmt>,
         resolving_function: FunctionType,
-    ) -> Result<(), String> {
+    ) -> Result<(), Diagnostic> {
         let enclosing_function = self.current_function;
         self.current_function = resolving_function;
         self.begin_scope();
@@ -211,17 +309,19 @@ impl Resolver {
         self.scopes.pop().expect("Estouro da pilha");
     }
 
-    fn declare(&mut self, name: &Token) -> Result<(), String> {
+    fn declare(&mut self, name: &Token) -> Result<(), Diagnostic> {
         let size = self.scopes.len();
         if self.scopes.is_empty() {
             return Ok(());
         }
 
         if self.scopes[size - 1].contains_key(&name.lexeme.clone()) {
-            return Err(format!("Já existe uma variável com este nome. [{}]", &name.lexeme.clone()));
+            let msg = format!("Já existe uma variável com este nome. [{}]", &name.lexeme.clone());
+            return Err(error("R003", msg, name).with_hint("Escolha outro nome ou atribua sem 'var'"));
         }
 
-        self.scopes[size - 1].insert(name.lexeme.clone(), false);
+        let slot = self.scopes[size - 1].len();
+        self.scopes[size - 1].insert(name.lexeme.clone(), (slot, false));
 
         Ok(())
     }
@@ -232,7 +332,16 @@ impl Resolver {
         }
 
         let size = self.scopes.len();
-        self.scopes[size - 1].insert(name.lexeme.clone(), true);
+        if let Some((_, defined)) = self.scopes[size - 1].get_mut(&name.lexeme) {
+            *defined = true;
+        }
+    }
+
+    /// Names the interpreter defines by itself, such as '_objeto' and 'super'
+    fn declare_implicit(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().expect("Não há escopo aberto");
+        let slot = scope.len();
+        scope.insert(name.to_string(), (slot, true));
     }
 
     // (i > j) may require different resolution distances
@@ -241,7 +350,7 @@ impl Resolver {
     // (i > 3) ->
     //         -> i -> try to resolve
     //         -> 3 -> try to resolve (trivial)
-    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), String> {
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
         match expr {
             Expr::Variable { id: _, name: _ } => self.resolve_expr_var(expr, expr.get_id()),
             Expr::Assign {
@@ -277,6 +386,84 @@ impl Resolver {
                 name: _,
             } => self.resolve_expr(object),
             Expr::Grouping { id: _, expression } => self.resolve_expr(expression),
+            Expr::Range {
+                id: _,
+                start,
+                operator: _,
+                end,
+                step,
+                inclusive: _,
+            } => {
+                self.resolve_expr(start)?;
+                self.resolve_expr(end)?;
+                if let Some(step) = step {
+                    self.resolve_expr(step)?;
+                }
+
+                Ok(())
+            }
+            Expr::Tuple { id: _, elements } => {
+                for element in elements {
+                    self.resolve_expr(element)?;
+                }
+
+                Ok(())
+            }
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => {
+                self.resolve_expr(value)?;
+                for target in targets {
+                    if let Expr::Variable { id, name } = target {
+                        self.resolve_local(name, *id)?;
+                    }
+                }
+
+                Ok(())
+            }
+            Expr::Array {
+                id: _,
+                name: _,
+                bounds,
+                element_type: _,
+            } => {
+                for (lower, upper) in bounds {
+                    self.resolve_expr(lower)?;
+                    self.resolve_expr(upper)?;
+                }
+
+                Ok(())
+            }
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                indices,
+            } => {
+                self.resolve_expr(object)?;
+                for index in indices {
+                    self.resolve_expr(index)?;
+                }
+
+                Ok(())
+            }
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket: _,
+                indices,
+                value,
+            } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(object)?;
+                for index in indices {
+                    self.resolve_expr(index)?;
+                }
+
+                Ok(())
+            }
             Expr::Literal { id: _, value: _ } => Ok(()),
             Expr::Logical {
                 id: _,
@@ -297,8 +484,13 @@ impl Resolver {
                 self.resolve_expr(object)
             }
             Expr::This { id: _, keyword } => {
+                if self.current_function == FunctionType::StaticMethod {
+                    let msg = "Não pode usar o comando '_objeto' dentro de um método estático";
+                    return Err(error("R004", msg, keyword));
+                }
                 if self.current_function != FunctionType::Method {
-                    return Err("Não pode usar o comando '_objeto' sem ser dentro de uma classe".to_string());
+                    let msg = "Não pode usar o comando '_objeto' sem ser dentro de uma classe";
+                    return Err(error("R004", msg, keyword));
                 }
                 self.resolve_local(keyword, expr.get_id())
             }
@@ -307,11 +499,16 @@ impl Resolver {
                 keyword,
                 method: _,
             } => {
+                if self.current_function == FunctionType::StaticMethod {
+                    let msg = "Não pode usar o comando 'super' dentro de um método estático";
+                    return Err(error("R004", msg, keyword));
+                }
                 if self.current_function != FunctionType::Method {
-                    return Err("Não pode usar o comando 'super' sem ser dentro de uma classe".to_string());
+                    let msg = "Não pode usar o comando 'super' sem ser dentro de uma classe";
+                    return Err(error("R004", msg, keyword));
                 }
                 if self.scopes.len() < 3 || !self.scopes[self.scopes.len() - 3].contains_key("super") {
-                    return Err("O objeto não possui uma classe pai".to_string());
+                    return Err(error("R005", "O objeto não possui uma classe pai", keyword));
                 }
                 self.resolve_local(keyword, expr.get_id())
             }
@@ -327,18 +524,20 @@ impl Resolver {
                 body,
             } => self.resolve_function_helper(
                 arguments,
-                &body.iter().map(|b| b.as_ref()).collect(),
+                &body.iter().collect(),
                 FunctionType::Function,
             ),
         }
     }
 
-    fn resolve_expr_var(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), String> {
+    fn resolve_expr_var(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), Diagnostic> {
         match expr {
             Expr::Variable { id: _, name } => {
                 if !self.scopes.is_empty() {
-                    if let Some(false) = self.scopes[self.scopes.len() - 1].get(&name.lexeme) {
-                        return Err("A variável local ainda não foi inicializada".to_string());
+                    if let Some((_, false)) = self.scopes[self.scopes.len() - 1].get(&name.lexeme) {
+                        let msg = "A variável local ainda não foi inicializada";
+                        return Err(error("R006", msg, name)
+                            .with_hint("Uma variável não pode ser usada no próprio valor inicial"));
                     }
                 }
 
@@ -357,16 +556,21 @@ impl Resolver {
         }
     }
 
-    fn resolve_local(&mut self, name: &Token, resolve_id: usize) -> Result<(), String> {
+    fn resolve_local(&mut self, name: &Token, resolve_id: usize) -> Result<(), Diagnostic> {
         let size = self.scopes.len();
         if size == 0 {
             return Ok(());
         }
 
         for i in (0..=(size - 1)).rev() {
-            let scope = &self.scopes[i];
-            if scope.contains_key(&name.lexeme) {
-                self.locals.insert(resolve_id, size - 1 - i);
+            if let Some((slot, _)) = self.scopes[i].get(&name.lexeme) {
+                self.locals.insert(
+                    resolve_id,
+                    LocalSlot {
+                        distance: size - 1 - i,
+                        index: *slot,
+                    },
+                );
                 return Ok(());
             }
         }
@@ -375,7 +579,7 @@ impl Resolver {
         Ok(())
     }
 
-    fn resolve_expr_assign(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), String> {
+    fn resolve_expr_assign(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), Diagnostic> {
         if let Expr::Assign { id: _, name, value } = expr {
             self.resolve_expr(value.as_ref())?;
             self.resolve_local(name, resolve_id)?;
@@ -386,3 +590,8 @@ impl Resolver {
         Ok(())
     }
 }
+
+/// A problem with the names of the program, pointing at `token`
+fn error(code: &'static str, msg: impl Into<String>, token: &Token) -> Diagnostic {
+    Diagnostic::new(Phase::Semantica, code, msg).at_token(token)
+}
@@ -1,13 +1,14 @@
+use crate::decimal::Decimal;
+use crate::diagnostic::{Diagnostic, Phase, Span};
 use std::collections::HashMap;
 use std::string::String;
 
 fn is_digit(ch: char) -> bool {
-    ch as u8 >= '0' as u8 && ch as u8 <= '9' as u8
+    ch.is_ascii_digit()
 }
 
 fn is_alpha(ch: char) -> bool {
-    let uch = ch as u8;
-    (uch >= 'a' as u8 && uch <= 'z' as u8) || (uch >= 'A' as u8 && uch <= 'Z' as u8) || (ch == '_')
+    ch.is_ascii_alphabetic() || ch == '_'
 }
 
 fn is_alpha_numeric(ch: char) -> bool {
@@ -38,11 +39,15 @@ fn getkeywords_hashmap() -> HashMap<&'static str, TokenType> {
 }
 
 pub struct Scanner {
-    source: String,
+    // Characters rather than bytes, so accented text in strings and comments is indexed right
+    source: Vec<char>,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    // Where the current line and the current token start, for the columns of tokens
+    line_start: usize,
+    column: usize,
 
     keywords: HashMap<&'static str, TokenType>,
 }
@@ -50,19 +55,22 @@ pub struct Scanner {
 impl Scanner {
     pub fn new(source: &str) -> Self {
         Self {
-            source: source.to_string(),
+            source: source.chars().collect(),
             tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 1,
             keywords: getkeywords_hashmap(),
         }
     }
 
-    pub fn scan_tokens(self: &mut Self) -> Result<Vec<Token>, String> {
+    pub fn scan_tokens(self: &mut Self) -> Result<Vec<Token>, Vec<Diagnostic>> {
         let mut errors = vec![];
         while !self.is_at_end() {
             self.start = self.current;
+            self.column = self.start - self.line_start + 1;
             match self.scan_token() {
                 Ok(_) => (),
                 Err(msg) => errors.push(msg),
@@ -74,15 +82,11 @@ impl Scanner {
             lexeme: "".to_string(),
             literal: None,
             line_number: self.line,
+            column: self.current - self.line_start + 1,
         });
 
         if errors.len() > 0 {
-            let mut joined = "".to_string();
-            for error in errors {
-                joined.push_str(&error);
-                joined.push_str("\n");
-            }
-            return Err(joined);
+            return Err(errors);
         }
 
         Ok(self.tokens.clone())
@@ -94,7 +98,7 @@ impl Scanner {
         self.current >= self.source.len()
     }
 
-    fn scan_token(self: &mut Self) -> Result<(), String> {
+    fn scan_token(self: &mut Self) -> Result<(), Diagnostic> {
         let c = self.advance();
 
         match c {
@@ -102,12 +106,29 @@ impl Scanner {
             ')' => self.add_token(RightParen),
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
             ',' => self.add_token(Comma),
-            '.' => self.add_token(Dot),
+            ':' => self.add_token(Colon),
+            '.' => {
+                let token = if self.char_match('.') {
+                    if self.char_match('=') {
+                        // ..=
+                        DotDotEqual
+                    } else {
+                        // ..
+                        DotDot
+                    }
+                } else {
+                    Dot
+                };
+                self.add_token(token);
+            }
             '-' => self.add_token(Minus),
             '+' => self.add_token(Plus),
             ';' => self.add_token(Semicolon),
             '*' => self.add_token(Star),
+            '&' => self.add_token(Ampersand),
             '~' => {
                 let token = if self.char_match('=') {
                     // !=
@@ -129,6 +150,8 @@ impl Scanner {
             '<' => {
                 let token = if self.char_match('=') {
                     LessEqual
+                } else if self.char_match('<') {
+                    LessLess
                 } else if self.char_match('-') {
                     Gets
                 } else {
@@ -140,6 +163,8 @@ impl Scanner {
             '>' => {
                 let token = if self.char_match('=') {
                     GreaterEqual
+                } else if self.char_match('>') {
+                    GreaterGreater
                 } else {
                     Greater
                 };
@@ -159,14 +184,18 @@ impl Scanner {
                 }
             },
             '|' => {
+                // |> chains calls, a lone | is the bitwise or
                 if self.char_match('>') {
                     self.add_token(Pipe);
                 } else {
-                    return Err(format!("Faltou o símbolo '>' na linha {}", self.line));
+                    self.add_token(Bar);
                 }
             },
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => {
+                self.line += 1;
+                self.line_start = self.current;
+            }
             '"' => self.string()?,
 
             c => {
@@ -175,7 +204,11 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    return Err(format!("Caractere não reconhecido na linha {}: {}", self.line, c));
+                    let error = self.error("L001", format!("Caractere não reconhecido: {c}"));
+                    if c.is_alphabetic() {
+                        return Err(error.with_hint("Nomes usam só letras sem acento, números e '_'"));
+                    }
+                    return Err(error);
                 }
             }
         }
@@ -188,15 +221,15 @@ impl Scanner {
             self.advance();
         }
 
-        let substring = &self.source[self.start..self.current];
-        if let Some(&t_type) = self.keywords.get(substring) {
+        let substring = self.text(self.start, self.current);
+        if let Some(&t_type) = self.keywords.get(substring.as_str()) {
             self.add_token(t_type);
         } else {
             self.add_token(Identifier);
         }
     }
 
-    fn number(self: &mut Self) -> Result<(), String> {
+    fn number(self: &mut Self) -> Result<(), Diagnostic> {
         while is_digit(self.peek()) {
             self.advance();
         }
@@ -208,11 +241,28 @@ impl Scanner {
                 self.advance();
             }
         }
-        let substring = &self.source[self.start..self.current];
+        let substring = self.text(self.start, self.current);
+
+        // 10.50d is an exact decimal
+        if self.peek() == 'd' && !is_alpha_numeric(self.peek_next()) {
+            let digits = substring.to_string();
+            self.advance();
+            return match Decimal::parse(&digits) {
+                Ok(_) => {
+                    self.add_token_lit(DecimalLit, Some(StringValue(digits)));
+                    Ok(())
+                }
+                Err(msg) => Err(self.error("L002", msg)),
+            };
+        }
+
         let value = substring.parse::<f64>();
         match value {
             Ok(value) => self.add_token_lit(Number, Some(FValue(value))),
-            Err(_) => return Err(format!("Não foi possível converter o número: {}", substring)),
+            Err(_) => {
+                let msg = format!("Não foi possível converter o número: {}", substring);
+                return Err(self.error("L002", msg));
+            }
         }
 
         Ok(())
@@ -223,26 +273,36 @@ impl Scanner {
             return '\0';
         }
 
-        self.source.chars().nth(self.current + 1).unwrap()
+        self.source[self.current + 1]
     }
 
-    fn string(self: &mut Self) -> Result<(), String> {
+    fn string(self: &mut Self) -> Result<(), Diagnostic> {
+        // An unterminated text is reported where it starts
+        let (line, column) = (self.line, self.column);
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
 
         if self.is_at_end() {
-            return Err("Texto sem marcador de término".to_string());
+            let span = Span {
+                line,
+                start: column,
+                end: column + 1,
+            };
+            return Err(Diagnostic::new(Phase::Lexica, "L003", "Texto sem marcador de término")
+                .at(span)
+                .with_hint("Feche o texto com \""));
         }
 
         self.advance();
 
-        let value = &self.source[self.start + 1..self.current - 1];
+        let value = self.text(self.start + 1, self.current - 1);
 
-        self.add_token_lit(StringLit, Some(StringValue(value.to_string())));
+        self.add_token_lit(StringLit, Some(StringValue(value)));
 
         Ok(())
     }
@@ -251,14 +311,14 @@ impl Scanner {
         if self.is_at_end() {
             return '\0';
         }
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current]
     }
 
     fn char_match(self: &mut Self, ch: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.chars().nth(self.current).unwrap() != ch {
+        if self.source[self.current] != ch {
             return false;
         } else {
             self.current += 1;
@@ -267,7 +327,7 @@ impl Scanner {
     }
 
     fn advance(self: &mut Self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
+        let c = self.source[self.current];
         self.current += 1;
 
         c
@@ -278,15 +338,30 @@ impl Scanner {
     }
 
     fn add_token_lit(self: &mut Self, token_type: TokenType, literal: Option<LiteralValue>) {
-        let text = self.source[self.start..self.current].to_string();
+        let text = self.text(self.start, self.current);
 
         self.tokens.push(Token {
             token_type: token_type,
             lexeme: text,
             literal: literal,
             line_number: self.line,
+            column: self.column,
         });
     }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        self.source[start..end].iter().collect()
+    }
+
+    /// A problem with the text of the current token
+    fn error(&self, code: &'static str, msg: String) -> Diagnostic {
+        let span = Span {
+            line: self.line,
+            start: self.column,
+            end: self.column + (self.current - self.start).max(1),
+        };
+        Diagnostic::new(Phase::Lexica, code, msg).at(span)
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -296,13 +371,17 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Ampersand,
 
     // One Or Two Chars
     Bang,
@@ -314,12 +393,18 @@ pub enum TokenType {
     Less,
     LessEqual,
     Pipe, // |>
+    Bar, // |
     Gets, // <-
+    LessLess, // <<
+    GreaterGreater, // >>
+    DotDot, // ..
+    DotDotEqual, // ..=
 
     // Literals
     Identifier,
     StringLit,
     Number,
+    DecimalLit,
 
     // Keywords
     And,
@@ -339,6 +424,10 @@ pub enum TokenType {
     Var,
     While,
     Limpar,
+    // Words such as 'de', 'produz' or 'xor' are names to the scanner; the parser reads them
+    // as keywords only where nothing else could go, and gives these two operators their type
+    Xor,
+    Compl,
 
     Eof,
 }
@@ -363,6 +452,8 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<LiteralValue>,
     pub line_number: usize,
+    // Counted from 1, in characters
+    pub column: usize,
 }
 
 impl Token {
@@ -424,11 +515,28 @@ mod tests {
         let mut scanner = Scanner::new(source);
         let result = scanner.scan_tokens();
         match result {
-            Err(_) => (),
+            Err(errors) => assert_eq!(errors[0].columns, Some((1, 2))),
             _ => panic!("Campo faltando"),
         }
     }
 
+    #[test]
+    fn records_token_columns() {
+        let source = "var a = 1;\n  saida a;";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let columns: Vec<(usize, usize)> = scanner
+            .tokens
+            .iter()
+            .map(|token| (token.line_number, token.column))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![(1, 1), (1, 5), (1, 7), (1, 9), (1, 10), (2, 3), (2, 9), (2, 10), (2, 11)]
+        );
+    }
+
     #[test]
     fn handle_string_lit_multiline() {
         let source = "\"ABC\ndef\"";
@@ -466,6 +574,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bitwise_tokens() {
+        let source = "a & b | c xor compl d << 2 >> 1 |> f";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 15);
+        assert_eq!(scanner.tokens[1].token_type, Ampersand);
+        assert_eq!(scanner.tokens[3].token_type, Bar);
+        assert_eq!(scanner.tokens[5].token_type, Identifier);
+        assert_eq!(scanner.tokens[6].token_type, Identifier);
+        assert_eq!(scanner.tokens[8].token_type, LessLess);
+        assert_eq!(scanner.tokens[10].token_type, GreaterGreater);
+        assert_eq!(scanner.tokens[12].token_type, Pipe);
+    }
+
+    #[test]
+    fn decimal_literals() {
+        let source = "10.50d 3d de";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 4);
+        assert_eq!(scanner.tokens[0].token_type, DecimalLit);
+        assert_eq!(scanner.tokens[1].token_type, DecimalLit);
+        assert_eq!(scanner.tokens[2].token_type, Identifier);
+        match &scanner.tokens[0].literal {
+            Some(StringValue(val)) => assert_eq!(val, "10.50"),
+            _ => panic!("Tipo incorreto"),
+        }
+    }
+
     #[test]
     fn get_identifer() {
         let source = "valor = 12;";
@@ -519,4 +659,53 @@ mod tests {
         assert_eq!(scanner.tokens[4].token_type, Semicolon);
         assert_eq!(scanner.tokens[5].token_type, Eof);
     }
+
+    #[test]
+    fn array_declaration_tokens() {
+        let source = "var m: matriz[1..3, 0..2] de real;";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 17);
+
+        assert_eq!(scanner.tokens[0].token_type, Var);
+        assert_eq!(scanner.tokens[1].token_type, Identifier);
+        assert_eq!(scanner.tokens[2].token_type, Colon);
+        assert_eq!(scanner.tokens[3].token_type, Identifier);
+        assert_eq!(scanner.tokens[4].token_type, LeftBracket);
+        assert_eq!(scanner.tokens[5].token_type, Number);
+        assert_eq!(scanner.tokens[6].token_type, DotDot);
+        assert_eq!(scanner.tokens[7].token_type, Number);
+        assert_eq!(scanner.tokens[8].token_type, Comma);
+        assert_eq!(scanner.tokens[9].token_type, Number);
+        assert_eq!(scanner.tokens[10].token_type, DotDot);
+        assert_eq!(scanner.tokens[11].token_type, Number);
+        assert_eq!(scanner.tokens[12].token_type, RightBracket);
+        assert_eq!(scanner.tokens[13].token_type, Identifier);
+        assert_eq!(scanner.tokens[14].token_type, Identifier);
+        assert_eq!(scanner.tokens[15].token_type, Semicolon);
+        assert_eq!(scanner.tokens[16].token_type, Eof);
+    }
+
+    #[test]
+    fn range_tokens() {
+        let source = "para i de 1 ate 10 passo 2 1..=3";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 12);
+
+        assert_eq!(scanner.tokens[0].token_type, For);
+        assert_eq!(scanner.tokens[1].token_type, Identifier);
+        assert_eq!(scanner.tokens[2].token_type, Identifier);
+        assert_eq!(scanner.tokens[3].token_type, Number);
+        assert_eq!(scanner.tokens[4].token_type, Identifier);
+        assert_eq!(scanner.tokens[5].token_type, Number);
+        assert_eq!(scanner.tokens[6].token_type, Identifier);
+        assert_eq!(scanner.tokens[7].token_type, Number);
+        assert_eq!(scanner.tokens[8].token_type, Number);
+        assert_eq!(scanner.tokens[9].token_type, DotDotEqual);
+        assert_eq!(scanner.tokens[10].token_type, Number);
+        assert_eq!(scanner.tokens[11].token_type, Eof);
+    }
 }
@@ -16,13 +16,35 @@ pub enum Stmt {
         name: Token,
         initializer: Expr,
     },
+    VarTuple {
+        names: Vec<Token>,
+        initializer: Expr,
+    },
     Block {
-        statements: Vec<Box<Stmt>>,
+        statements: Vec<Stmt>,
     },
     Class {
         name: Token,
-        methods: Vec<Box<Stmt>>,
+        methods: Vec<Stmt>,
+        // `campo nome = valor`, with the statement `_objeto.nome = valor;` that sets it
+        fields: Vec<(Token, Stmt)>,
+        // Only declared fields can be assigned (`estrita classe`)
+        strict: bool,
         superclass: Option<Expr>,
+        static_methods: Vec<Stmt>,
+        static_fields: Vec<(Token, Expr)>,
+        getters: Vec<Stmt>,
+        setters: Vec<Stmt>,
+        contracts: Vec<Expr>,
+        mixins: Vec<Expr>,
+    },
+    Mixin {
+        name: Token,
+        methods: Vec<Stmt>,
+    },
+    Contract {
+        name: Token,
+        methods: Vec<(Token, usize)>,
     },
     IfStmt {
         predicate: Expr,
@@ -33,10 +55,15 @@ pub enum Stmt {
         condition: Expr,
         body: Box<Stmt>,
     },
+    ForRange {
+        variable: Token,
+        range: Expr,
+        body: Box<Stmt>,
+    },
     Function {
         name: Token,
         params: Vec<Token>,
-        body: Vec<Box<Stmt>>,
+        body: Vec<Stmt>,
     },
     CmdFunction {
         name: Token,
@@ -46,12 +73,52 @@ pub enum Stmt {
         keyword: Token,
         value: Option<Expr>,
     },
+    Yield {
+        keyword: Token,
+        value: Expr,
+    },
 }
 
 impl Stmt {
+    /// The token an error raised by the statement itself points at
+    pub fn token(&self) -> Option<&Token> {
+        use Stmt::*;
+        match self {
+            Var { name, .. }
+            | Class { name, .. }
+            | Mixin { name, .. }
+            | Contract { name, .. }
+            | Function { name, .. }
+            | CmdFunction { name, .. } => Some(name),
+            ForRange { variable, .. } => Some(variable),
+            ReturnStmt { keyword, .. } | Yield { keyword, .. } => Some(keyword),
+            Expression { expression } | Print { expression } | Limpar { expression } => {
+                expression.token()
+            }
+            IfStmt { predicate, .. } => predicate.token(),
+            WhileStmt { condition, .. } => condition.token(),
+            Block { statements } => statements.iter().find_map(|stmt| stmt.token()),
+            _ => None,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn to_string(&self) -> String {
         use Stmt::*;
+        let many = |stmts: &Vec<Stmt>| {
+            stmts
+                .iter()
+                .map(|stmt| stmt.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        let names = |tokens: &Vec<Token>| {
+            tokens
+                .iter()
+                .map(|token| token.lexeme.clone())
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
         match self {
             Expression { expression } => expression.to_string(),
             Print { expression } => format!("(saida {})", expression.to_string()),
@@ -60,30 +127,57 @@ impl Stmt {
                 name,
                 initializer: _,
             } => format!("(var {})", name.lexeme),
-            Block { statements } => format!(
-                "(block {})",
-                statements
-                    .into_iter()
-                    .map(|stmt| stmt.to_string())
-                    .collect::<String>()
+            VarTuple {
+                names: targets,
+                initializer,
+            } => format!("(var ({}) {})", names(targets), initializer.to_string()),
+            Block { statements } => format!("(block {})", many(statements)),
+            Class { name, methods, .. } => format!("(classe {} {})", name.lexeme, many(methods)),
+            Mixin { name, methods } => format!("(mixin {} {})", name.lexeme, many(methods)),
+            Contract { name, methods } => format!(
+                "(contrato {} {})",
+                name.lexeme,
+                methods
+                    .iter()
+                    .map(|(method, arity)| format!("{}/{arity}", method.lexeme))
+                    .collect::<Vec<String>>()
+                    .join(" ")
             ),
             IfStmt {
-                predicate: _,
-                then: _,
-                els: _,
-            } => todo!(),
-            WhileStmt {
-                condition: _,
-                body: _,
-            } => todo!(),
-            Function {
-                name: _,
-                params: _,
-                body: _,
-            } => todo!(),
-            CmdFunction { name: _, cmd: _ } => todo!(),
-            ReturnStmt { keyword: _, value: _ } => todo!(),
-            _ => todo!(),
+                predicate,
+                then,
+                els,
+            } => match els {
+                Some(els) => format!(
+                    "(se {} {} senao {})",
+                    predicate.to_string(),
+                    then.to_string(),
+                    els.to_string()
+                ),
+                None => format!("(se {} {})", predicate.to_string(), then.to_string()),
+            },
+            WhileStmt { condition, body } => {
+                format!("(enquanto {} {})", condition.to_string(), body.to_string())
+            }
+            ForRange {
+                variable,
+                range,
+                body,
+            } => format!(
+                "(para {} em {} {})",
+                variable.lexeme,
+                range.to_string(),
+                body.to_string()
+            ),
+            Function { name, params, body } => {
+                format!("(fun {} ({}) {})", name.lexeme, names(params), many(body))
+            }
+            CmdFunction { name, cmd } => format!("(fun {} <- {cmd})", name.lexeme),
+            ReturnStmt { keyword: _, value } => match value {
+                Some(value) => format!("(retorna {})", value.to_string()),
+                None => "(retorna)".to_string(),
+            },
+            Yield { keyword: _, value } => format!("(produz {})", value.to_string()),
         }
     }
 }
@@ -0,0 +1,256 @@
+use crate::budget;
+use crate::diagnostic::RuntimeError;
+use crate::expr::{Arity, CallableImpl, LiteralValue, NativeFunctionImpl};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The longest text `repetir` makes, so a typo in the count is an error and not a crash
+const MAX_REPEATED_BYTES: usize = 100_000_000;
+
+// `repetir` makes its text this many bytes at a time, counting each block against the limits
+const REPEAT_BLOCK_BYTES: usize = 1 << 16;
+
+/// Methods of texts, with the number of arguments each one receives besides the text
+const METHODS: [(&str, usize); 12] = [
+    ("tamanho", 0),
+    ("maiusculo", 0),
+    ("minusculo", 0),
+    ("aparar", 0),
+    ("contem", 1),
+    ("comeca_com", 1),
+    ("termina_com", 1),
+    ("substituir", 2),
+    ("dividir", 1),
+    ("sub", 2),
+    ("repetir", 1),
+    ("caractere", 1),
+];
+
+fn text_arg<'a>(method: &str, args: &'a [LiteralValue], i: usize) -> Result<&'a str, String> {
+    match &args[i] {
+        LiteralValue::StringValue(s) => Ok(s),
+        other => Err(format!(
+            "O método {method} esperava um texto, mas recebeu um {}",
+            other.to_type()
+        )),
+    }
+}
+
+fn integer_arg(method: &str, args: &[LiteralValue], i: usize) -> Result<i64, String> {
+    match &args[i] {
+        LiteralValue::Number(x) if x.fract() == 0.0 => Ok(*x as i64),
+        other => Err(format!(
+            "O método {method} esperava um número inteiro, mas recebeu {}",
+            other.to_string()
+        )),
+    }
+}
+
+/// The character at a 0-based position, counting characters and not bytes
+pub fn char_at(text: &str, index: i64) -> Result<LiteralValue, String> {
+    let count = text.chars().count();
+    if index < 0 || index as usize >= count {
+        return Err(format!(
+            "índice {index} fora dos limites do texto de {count} caracteres"
+        ));
+    }
+    let c = text.chars().nth(index as usize).unwrap_or_default();
+    Ok(LiteralValue::StringValue(c.to_string()))
+}
+
+/// Runs `texto.metodo(args)`
+pub fn call_string_method(method: &str, text: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    let arity = match METHODS.iter().find(|(name, _)| *name == method) {
+        Some((_, arity)) => *arity,
+        None => return Err(format!("O texto não possui o método {method}").into()),
+    };
+    if args.len() != arity {
+        return Err(format!(
+            "O método {method} esperava {arity} argumento(s) mas encontrou {}",
+            args.len()
+        ).into());
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let result = match method {
+        "tamanho" => LiteralValue::Number(chars.len() as f64),
+        "maiusculo" => LiteralValue::StringValue(text.to_uppercase()),
+        "minusculo" => LiteralValue::StringValue(text.to_lowercase()),
+        "aparar" => LiteralValue::StringValue(text.trim().to_string()),
+        "contem" => LiteralValue::from_bool(text.contains(text_arg(method, args, 0)?)),
+        "comeca_com" => LiteralValue::from_bool(text.starts_with(text_arg(method, args, 0)?)),
+        "termina_com" => LiteralValue::from_bool(text.ends_with(text_arg(method, args, 0)?)),
+        "substituir" => LiteralValue::StringValue(
+            text.replace(text_arg(method, args, 0)?, text_arg(method, args, 1)?),
+        ),
+        "dividir" => {
+            let separator = text_arg(method, args, 0)?;
+            // An empty separator splits the text into its characters
+            let parts: Vec<LiteralValue> = if separator.is_empty() {
+                chars
+                    .iter()
+                    .map(|c| LiteralValue::StringValue(c.to_string()))
+                    .collect()
+            } else {
+                text.split(separator)
+                    .map(|part| LiteralValue::StringValue(part.to_string()))
+                    .collect()
+            };
+            LiteralValue::Tuple(parts)
+        }
+        "sub" => {
+            // Characters from inicio up to, but not including, fim
+            let start = integer_arg(method, args, 0)?;
+            let end = integer_arg(method, args, 1)?;
+            if start < 0 || end < start || end as usize > chars.len() {
+                return Err(format!(
+                    "O intervalo [{start}..{end}] está fora do texto de {} caracteres",
+                    chars.len()
+                ).into());
+            }
+            LiteralValue::StringValue(chars[start as usize..end as usize].iter().collect())
+        }
+        "repetir" => {
+            let times = integer_arg(method, args, 0)?;
+            if times < 0 {
+                return Err(format!("Não é possível repetir um texto {times} vezes").into());
+            }
+            match text.len().checked_mul(times as usize) {
+                Some(bytes) if bytes <= MAX_REPEATED_BYTES => {
+                    LiteralValue::StringValue(repeat(text, times as usize)?)
+                }
+                _ => {
+                    return Err(format!(
+                        "O texto repetido {} vezes teria mais que {MAX_REPEATED_BYTES} bytes",
+                        args[0].to_string()
+                    ).into())
+                }
+            }
+        }
+        _ => char_at(text, integer_arg(method, args, 0)?)?,
+    };
+
+    Ok(result)
+}
+
+/// `text.repeat(times)` a block at a time, so the limits can stop a long text being made
+fn repeat(text: &str, times: usize) -> Result<String, RuntimeError> {
+    if text.is_empty() {
+        return Ok(String::new());
+    }
+    let per_block = (REPEAT_BLOCK_BYTES / text.len()).max(1);
+    let block = text.repeat(per_block.min(times));
+    let mut repeated = String::with_capacity(text.len() * times);
+    let mut left = times;
+    while left >= per_block {
+        repeated.push_str(&block);
+        budget::charge(block.len() as u64 / budget::WORK_PER_STEP)?;
+        left -= per_block;
+    }
+    repeated.push_str(&text.repeat(left));
+    Ok(repeated)
+}
+
+/// `nome.maiusculo` as a function with the receiver already in place
+pub fn bound_string_method(method: &str, text: &str) -> Result<LiteralValue, String> {
+    let arity = match METHODS.iter().find(|(name, _)| *name == method) {
+        Some((_, arity)) => *arity,
+        None => return Err(format!("O texto não possui o método {method}")),
+    };
+    let name = method.to_string();
+    let text = text.to_string();
+
+    Ok(LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+        name: method.to_string(),
+        arity: Arity::Exactly(arity),
+        fun: Rc::new(move |args: &Vec<LiteralValue>| call_string_method(&name, &text, args)),
+    })))
+}
+
+/// The `texto` module: every method as a function taking the text first, for pipelines
+/// such as `nome |> texto.aparar |> texto.maiusculo`
+pub fn string_module() -> LiteralValue {
+    let mut members = HashMap::new();
+    for (method, arity) in METHODS {
+        let fun = move |args: &Vec<LiteralValue>| match args.split_first() {
+            Some((LiteralValue::StringValue(text), rest)) => call_string_method(method, text, rest),
+            Some((other, _)) => Err(format!(
+                "texto.{method} esperava um texto, mas recebeu um {}",
+                other.to_type()
+            )
+            .into()),
+            None => Err(format!("texto.{method} esperava um texto").into()),
+        };
+        members.insert(
+            method.to_string(),
+            LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+                name: format!("texto.{method}"),
+                arity: Arity::Exactly(arity + 1),
+                fun: Rc::new(fun),
+            })),
+        );
+    }
+
+    LiteralValue::Namespace {
+        name: "texto".to_string(),
+        members: Rc::new(members),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(method: &str, text: &str, args: &[LiteralValue]) -> LiteralValue {
+        call_string_method(method, text, args).unwrap()
+    }
+
+    fn s(text: &str) -> LiteralValue {
+        LiteralValue::StringValue(text.to_string())
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        assert_eq!(call("tamanho", "ação", &[]), LiteralValue::Number(4.0));
+        assert_eq!(call("sub", "coração", &[LiteralValue::Number(4.0), LiteralValue::Number(7.0)]), s("ção"));
+        assert_eq!(char_at("pão", 1).unwrap(), s("ã"));
+        assert!(char_at("pão", 3).is_err());
+    }
+
+    #[test]
+    fn changes_case_and_trims() {
+        assert_eq!(call("maiusculo", "ação", &[]), s("AÇÃO"));
+        assert_eq!(call("minusculo", "FERRO", &[]), s("ferro"));
+        assert_eq!(call("aparar", "  fe  ", &[]), s("fe"));
+    }
+
+    #[test]
+    fn searches_and_replaces() {
+        assert_eq!(call("contem", "ferrugem", &[s("rug")]), LiteralValue::True);
+        assert_eq!(call("comeca_com", "ferrugem", &[s("x")]), LiteralValue::False);
+        assert_eq!(call("substituir", "a-b-c", &[s("-"), s("+")]), s("a+b+c"));
+    }
+
+    #[test]
+    fn splits_into_a_tuple() {
+        assert_eq!(
+            call("dividir", "a,b", &[s(",")]),
+            LiteralValue::Tuple(vec![s("a"), s("b")])
+        );
+        assert_eq!(
+            call("dividir", "pé", &[s("")]),
+            LiteralValue::Tuple(vec![s("p"), s("é")])
+        );
+    }
+
+    #[test]
+    fn checks_arguments() {
+        assert!(call_string_method("repetir", "a", &[LiteralValue::Number(-1.0)]).is_err());
+        assert!(call_string_method("repetir", "ab", &[LiteralValue::Number(1e19)]).is_err());
+        assert_eq!(call("repetir", "ab", &[LiteralValue::Number(2.0)]), s("abab"));
+        assert_eq!(call("repetir", "abc", &[LiteralValue::Number(50000.0)]), s(&"abc".repeat(50000)));
+        assert_eq!(call("repetir", "", &[LiteralValue::Number(1e15)]), s(""));
+        assert!(call_string_method("contem", "a", &[]).is_err());
+        assert!(call_string_method("inverter", "a", &[]).is_err());
+    }
+}
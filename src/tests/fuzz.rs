@@ -0,0 +1,285 @@
+//! Feeds the binary random token streams and mutated test cases: whatever the program, it
+//! must answer with output or an error message, never with a panic, in both engines.
+//!
+//! FE_FUZZ_ITERACOES sets how many programs of each kind are tried (default 150).
+
+use crate::parser::MAX_NESTING;
+use std::fs::{read_dir, read_to_string};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const VOCABULARY: &[&str] = &[
+    "var", "fun", "classe", "mixin", "contrato", "implementa", "usa", "se", "senao", "enquanto",
+    "para", "de", "ate", "passo", "em", "retorna", "produz", "saida", "limpar", "super",
+    "_objeto", "estatico", "obter", "definir", "campo", "estrita", "vetor", "matriz", "e", "ou", "xor", "compl",
+    "verdadeiro", "falso", "vazio", "a", "b", "f", "A", "B", "ini", "texto", "mat", "argumentos",
+    "tamanho", "0", "1", "2.5", "10d", "-1", "\"texto\"", "\"\"", "(", ")", "{", "}", "[", "]",
+    ",", ".", ";", ":", "..", "..=", "+", "-", "*", "/", "=", "==", "~=", "~", "<", "<=", ">",
+    ">=", "<<", ">>", "&", "|", "|>", "\n", "_soma", "_igual", "_menor", "_texto", "arredondar",
+    "binario", "hex", "decimal", "repetir", "100000", "10000000000000000000",
+];
+
+/// Calls of built-ins, with each `{}` replaced by one of EXTREMES
+const CALLS: &[&str] = &[
+    "saida arredondar({}, {});",
+    "saida arredondar(decimal({}), {});",
+    "saida arredondar(decimal(\"0.99999999999999999999999999999999999999\"), {});",
+    "saida arredondar({}d, {}, \"meio_acima\");",
+    "saida mat.arredondar({}, {});",
+    "saida mat.fatorial({});",
+    "saida mat.potencia({}, {});",
+    "saida binario({}, {});",
+    "saida hex({}, {});",
+    "saida \"ab\".repetir({});",
+    "saida \"texto\".sub({}, {});",
+    "saida \"texto\".caractere({});",
+    "saida decimal({}) * decimal({});",
+    "saida {} << {};",
+    "var v: vetor[1..{}] de inteiro;",
+    "var m: matriz[{}..{}, 1..{}] de real;",
+];
+
+const EXTREMES: &[&str] = &[
+    "0", "-1", "0.5", "65536", "100000", "4294967296", "9007199254740993", "10000000000000000000",
+    "-10000000000000000000", "1e308", "-1e308", "\"99999999999999999999999999999999999999\"",
+    "\"\"", "vazio",
+];
+
+/// Programs that recurse without end through calls the program doesn't write: properties,
+/// operators, `_texto` and field initializers
+const RECURSIONS: &[&str] = &[
+    "classe A { obter x() { retorna _objeto.x; } } saida A().x;",
+    "classe A { definir x(v) { _objeto.x = v; } } var a = A(); a.x = 1;",
+    "classe A { _soma(o) { retorna _objeto + o; } } saida A() + 1;",
+    "classe A { _menor(o) { retorna _objeto < o; } } saida A() < 1;",
+    "classe A { _igual(o) { retorna _objeto ~= o; } } saida A() == 1;",
+    "classe A { _texto() { retorna \"a\" + _objeto; } } saida A();",
+    "classe A { campo proximo = A(); } A();",
+    "classe A { ini() { A(); } } A();",
+    "fun f(n) { retorna f(n + 1); } f(0);",
+];
+
+/// Shapes the parser nests into, as (before, opening, innermost, closing, after)
+const NESTINGS: &[(&str, &str, &str, &str, &str)] = &[
+    ("saida ", "(", "1", ")", ";"),
+    ("saida ", "-", "1", "", ";"),
+    ("saida ", "mat.abs(", "1", ")", ";"),
+    ("", "{", "saida 1;", "}", ""),
+    ("", "fun f() {", "saida 1;", "}", ""),
+    ("", "se (verdadeiro) ", "saida 1;", "", ""),
+    ("saida ", "(fun () { retorna ", "1", "; })()", ";"),
+];
+
+/// Small deterministic generator, so a failure can be reproduced
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn word(&mut self) -> &'static str {
+        VOCABULARY[self.below(VOCABULARY.len())]
+    }
+}
+
+fn iterations() -> usize {
+    std::env::var("FE_FUZZ_ITERACOES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(150)
+}
+
+/// Builds the binary and returns its path
+fn binary() -> PathBuf {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let mut build = Command::new("cargo");
+    build.args(["build", "-q", "--manifest-path", manifest]);
+    if !cfg!(debug_assertions) {
+        build.arg("--release");
+    }
+    assert!(build.status().unwrap().success(), "Não foi possível compilar o fe");
+
+    // The test runs from target/<perfil>/deps
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().parent().unwrap().join("fe")
+}
+
+/// Runs a program and returns what it wrote to stderr if it panicked. Programs that run for
+/// too long (an endless loop made by a mutation) are stopped and count as fine.
+fn panics(fe: &PathBuf, program: &str, vm: bool) -> Option<String> {
+    let mut child = Command::new(fe)
+        .args(if vm { &["--vm"][..] } else { &[] })
+        .arg("e")
+        .arg(program)
+        .current_dir(std::env::temp_dir())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(5) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        sleep(Duration::from_millis(2));
+    };
+
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    // A native stack overflow aborts the process instead of panicking
+    if status.code() == Some(101) || status.code().is_none() || stderr.contains("panicked") {
+        Some(stderr)
+    } else {
+        None
+    }
+}
+
+fn random_program(rng: &mut Rng) -> String {
+    let length = 1 + rng.below(40);
+    (0..length).map(|_| rng.word()).collect::<Vec<&str>>().join(" ")
+}
+
+/// A test case with a few characters deleted, repeated or replaced by tokens
+fn mutate(rng: &mut Rng, program: &str) -> String {
+    let mut chars: Vec<char> = program.chars().collect();
+    for _ in 0..1 + rng.below(3) {
+        let at = rng.below(chars.len() + 1);
+        let end = (at + 1 + rng.below(10)).min(chars.len());
+        match rng.below(4) {
+            0 => {
+                chars.drain(at..end);
+            }
+            1 => {
+                let copy: Vec<char> = chars[at..end].to_vec();
+                chars.splice(at..at, copy);
+            }
+            2 => {
+                let word: Vec<char> = format!(" {} ", rng.word()).chars().collect();
+                chars.splice(at..at, word);
+            }
+            _ => chars.truncate(at),
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// A built-in called with numbers at or past the edges of what it accepts
+fn extreme_call(rng: &mut Rng) -> String {
+    let call = CALLS[rng.below(CALLS.len())];
+    let mut program = String::new();
+    for (i, part) in call.split("{}").enumerate() {
+        if i > 0 {
+            program.push_str(EXTREMES[rng.below(EXTREMES.len())]);
+        }
+        program.push_str(part);
+    }
+    program
+}
+
+fn cases() -> Vec<String> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/cases");
+    let mut cases = vec![];
+    for case in read_dir(dir).unwrap() {
+        let path = case.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "fe") {
+            let code = read_to_string(&path).unwrap();
+            cases.push(
+                code.lines()
+                    .filter(|line| !line.starts_with("//"))
+                    .collect::<Vec<&str>>()
+                    .join("\n"),
+            );
+        }
+    }
+    cases.sort();
+    cases
+}
+
+#[test]
+fn never_panics_on_random_programs() {
+    let fe = binary();
+    let cases = cases();
+    let mut rng = Rng(0x5eed_f00d_cafe_0042);
+
+    let mut programs = vec![];
+    for _ in 0..iterations() {
+        programs.push(random_program(&mut rng));
+        let case = &cases[rng.below(cases.len())];
+        programs.push(mutate(&mut rng, case));
+    }
+
+    check(&fe, programs);
+}
+
+#[test]
+fn never_panics_on_extreme_calls_and_implicit_recursion() {
+    let fe = binary();
+    let mut rng = Rng(0x0dd_ba11_f00d_0047);
+
+    let mut programs: Vec<String> = RECURSIONS.iter().map(|program| program.to_string()).collect();
+    for _ in 0..iterations() {
+        programs.push(extreme_call(&mut rng));
+    }
+
+    check(&fe, programs);
+}
+
+/// Runs each program in both engines, failing with the first few that panicked
+#[test]
+fn never_panics_on_deep_nesting() {
+    let fe = binary();
+    let mut programs = vec![];
+    for (before, open, inner, close, after) in NESTINGS {
+        // Programs are passed as one argument, which the system caps at 128 KB
+        let deepest = 20_000.min(100_000 / (open.len() + close.len()));
+        for depth in [MAX_NESTING / 2, MAX_NESTING, deepest] {
+            programs.push(format!("{before}{}{inner}{}{after}", open.repeat(depth), close.repeat(depth)));
+        }
+    }
+
+    check(&fe, programs);
+}
+
+fn check(fe: &PathBuf, programs: Vec<String>) {
+    let mut failures = vec![];
+    for program in programs {
+        // Command functions (`fun f <- "..."`) would run programs of the machine
+        if program.contains("<-") {
+            continue;
+        }
+        for vm in [false, true] {
+            if let Some(stderr) = panics(fe, &program, vm) {
+                let engine = if vm { " (--vm)" } else { "" };
+                failures.push(format!("--- programa{engine}\n{program}\n--- erro\n{stderr}"));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!(
+            "{} programa(s) fizeram o fe entrar em pânico:\n\n{}",
+            failures.len(),
+            failures.iter().take(5).cloned().collect::<Vec<String>>().join("\n")
+        );
+    }
+}
@@ -1,3 +1,6 @@
+#[cfg(test)]
+mod fuzz;
+
 #[cfg(test)]
 mod tests {
     use std::fs::{read_dir, read_to_string, DirEntry};
@@ -105,7 +108,8 @@ mod tests {
         }
 
         for (i, expected) in expected_output.iter().enumerate() {
-            if lines[i] != (*expected).trim() {
+            // Both sides trimmed: the gutter of rendered errors starts with spaces
+            if lines[i].trim() != (*expected).trim() {
                 return Err(format!(
                     "{:#?}: {} != {}\nSaída completa:\n{}",
                     file.file_name(),
@@ -118,4 +122,54 @@ mod tests {
 
         Ok(())
     }
+
+    fn run_engine(code: &str, vm: bool) -> String {
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let mut command = Command::new("cargo");
+        command.args(["run", "-q", "--manifest-path", manifest, "--"]);
+        if vm {
+            command.arg("--vm");
+        }
+        // Outside the repository, so the interpreter's doc.md doesn't land in it
+        let output = command
+            .arg("e")
+            .arg(code)
+            .current_dir(std::env::temp_dir())
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    #[test]
+    fn vm_matches_interpreter() {
+        let cases = read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/cases")).unwrap();
+
+        let mut differences = vec![];
+        for case in cases {
+            let path = case.unwrap().path();
+            let name = path.display().to_string();
+            if name.contains("~") || !name.ends_with(".fe") {
+                continue;
+            }
+
+            let contents = read_to_string(&path).unwrap();
+            let code = contents
+                .lines()
+                .filter(|line| !line.starts_with("//"))
+                .collect::<Vec<&str>>()
+                .join("\n");
+
+            let expected = run_engine(&code, false);
+            let output = run_engine(&code, true);
+            if output != expected {
+                differences.push(format!(
+                    "{name}:\n--- interpretador\n{expected}--- vm\n{output}"
+                ));
+            }
+        }
+
+        if !differences.is_empty() {
+            panic!("A VM diverge do interpretador:\n\n{}", differences.join("\n\n"));
+        }
+    }
 }
@@ -0,0 +1,464 @@
+use crate::budget::{self, Limits};
+use crate::callstack;
+use crate::chunk::{FunctionProto, Op};
+use crate::compiler::Compiler;
+use crate::diagnostic::RuntimeError;
+use crate::environment::Environment;
+use crate::heap;
+use crate::expr::{
+    binary_op, call_value, destructure, get_index, get_property, iteration_value, make_array,
+    make_range, set_index, set_property, to_text, unary_op, CallableImpl, LiteralValue,
+};
+use crate::interpreter::{clear_screen, Interpreter};
+use crate::scanner::TokenType;
+use crate::stmt::Stmt;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A compiled function together with the variables it captured
+pub struct Closure {
+    pub proto: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<LiteralValue>>>,
+    pub globals: Environment,
+}
+
+/// A stack slot. Locals start as plain values and are moved into a shared cell the first
+/// time a closure captures them, so the closure and the function keep seeing the same variable.
+// Values stay inline: boxing every one would cost an allocation per push
+#[allow(clippy::large_enum_variant)]
+enum Slot {
+    Value(LiteralValue),
+    Cell(Rc<RefCell<LiteralValue>>),
+}
+
+impl Slot {
+    fn get(&self) -> LiteralValue {
+        match self {
+            Slot::Value(value) => value.clone(),
+            Slot::Cell(cell) => cell.borrow().clone(),
+        }
+    }
+
+    fn into_value(self) -> LiteralValue {
+        match self {
+            Slot::Value(value) => value,
+            Slot::Cell(cell) => cell.borrow().clone(),
+        }
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,
+}
+
+pub struct Vm {
+    stack: Vec<Slot>,
+    frames: Vec<Frame>,
+    // Just past the instruction being run by the innermost frame, to tell where errors happen
+    ip: usize,
+}
+
+/// Runs a resolved program with the bytecode VM, sharing the interpreter's globals and
+/// counting its steps, time and output against `limits` from zero
+pub fn interpret(stmts: &[Stmt], globals: Environment, limits: Limits) -> Result<(), RuntimeError> {
+    let Some(proto) = Compiler::compile(stmts)? else {
+        // A class or generator declared inside a function or block: the tree-walker runs it all
+        return Interpreter::with_env(globals).with_limits(limits).run(stmts);
+    };
+    budget::start(limits);
+    let script = heap::closure(Closure {
+        proto: Rc::new(proto),
+        upvalues: vec![],
+        globals,
+    });
+    call_closure(&script, vec![])?;
+    Ok(())
+}
+
+/// Calls a compiled function; also used when the tree-walker calls one (a method calling a
+/// function declared at the top level, for instance)
+pub fn call_closure(
+    closure: &Rc<Closure>,
+    arguments: Vec<LiteralValue>,
+) -> Result<LiteralValue, RuntimeError> {
+    check_arity(closure, arguments.len())?;
+    let mut vm = Vm {
+        stack: vec![Slot::Value(LiteralValue::Callable(CallableImpl::Compiled(
+            closure.clone(),
+        )))],
+        frames: vec![],
+        ip: 0,
+    };
+    vm.stack.extend(arguments.into_iter().map(Slot::Value));
+    vm.frames.push(Frame {
+        closure: closure.clone(),
+        ip: 0,
+        base: 0,
+    });
+    vm.run()
+}
+
+fn check_arity(closure: &Closure, count: usize) -> Result<(), String> {
+    if closure.proto.arity != count {
+        // Same text as the tree-walking interpreter
+        return Err(format!(
+            "A função  {} esperava os arugmentos {} mas encontrou {}",
+            closure.proto.name, closure.proto.arity, count
+        ));
+    }
+    Ok(())
+}
+
+impl Vm {
+    fn push(&mut self, value: LiteralValue) {
+        self.stack.push(Slot::Value(value));
+    }
+
+    fn pop(&mut self) -> LiteralValue {
+        self.stack
+            .pop()
+            .map(Slot::into_value)
+            .unwrap_or(LiteralValue::Nil)
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<LiteralValue> {
+        let start = self.stack.len() - count;
+        self.stack.drain(start..).map(Slot::into_value).collect()
+    }
+
+    fn peek(&self) -> LiteralValue {
+        self.stack
+            .last()
+            .map(Slot::get)
+            .unwrap_or(LiteralValue::Nil)
+    }
+
+    fn set_slot(&mut self, slot: usize, value: LiteralValue) {
+        match &mut self.stack[slot] {
+            Slot::Value(current) => *current = value,
+            Slot::Cell(cell) => *cell.borrow_mut() = value,
+        }
+    }
+
+    /// Moves a local into a shared cell, if it isn't in one already
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<LiteralValue>> {
+        if let Slot::Value(value) = &self.stack[slot] {
+            let cell = heap::variable(value.clone());
+            self.stack[slot] = Slot::Cell(cell);
+        }
+        match &self.stack[slot] {
+            Slot::Cell(cell) => cell.clone(),
+            Slot::Value(_) => unreachable!("a variável acabou de ser capturada"),
+        }
+    }
+
+    fn run(&mut self) -> Result<LiteralValue, RuntimeError> {
+        // Calls made inside this run are on the call stack until they return
+        let depth = callstack::depth();
+        self.execute().map_err(|error| {
+            callstack::fail();
+            callstack::unwind_to(depth);
+            match self.frames.last().filter(|_| self.ip > 0) {
+                Some(frame) => error.raised_at(frame.closure.proto.chunk.spans[self.ip - 1]),
+                None => error,
+            }
+        })
+    }
+
+    fn execute(&mut self) -> Result<LiteralValue, RuntimeError> {
+        let frame = self.frames.last().expect("A VM começa com uma chamada");
+        let mut closure = frame.closure.clone();
+        let mut ip = frame.ip;
+        let mut base = frame.base;
+
+        loop {
+            let chunk = &closure.proto.chunk;
+            let op = chunk.code[ip];
+            ip += 1;
+            self.ip = ip;
+            budget::step()?;
+
+            match op {
+                Op::Constant(index) => self.push(chunk.constants[index as usize].clone()),
+                Op::Nil => self.push(LiteralValue::Nil),
+                Op::True => self.push(LiteralValue::True),
+                Op::False => self.push(LiteralValue::False),
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Dup => {
+                    let value = self.peek();
+                    self.push(value);
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack[base + slot as usize].get();
+                    self.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.peek();
+                    self.set_slot(base + slot as usize, value);
+                }
+                Op::GetUpvalue(index) => {
+                    let value = closure.upvalues[index as usize].borrow().clone();
+                    self.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    *closure.upvalues[index as usize].borrow_mut() = self.peek();
+                }
+                Op::GetGlobal(index) => {
+                    let name = &chunk.names[index as usize];
+                    match closure.globals.get_global(name) {
+                        Some(value) => self.push(value),
+                        None => {
+                            return Err(format!(
+                                "A variável '{}' não foi declarada no ponto None",
+                                name
+                            ).into())
+                        }
+                    }
+                }
+                Op::SetGlobal(index) => {
+                    let name = &chunk.names[index as usize];
+                    if !closure.globals.assign_global(name, self.peek()) {
+                        return Err(format!("A variável {} não foi declarada", name).into());
+                    }
+                }
+                Op::DefineGlobal(index) => {
+                    let value = self.pop();
+                    closure
+                        .globals
+                        .define(chunk.names[index as usize].clone(), value);
+                }
+                Op::GetProperty(name) => {
+                    let object = self.pop();
+                    let value = get_property(object, &chunk.tokens[name as usize])?;
+                    self.push(value);
+                }
+                Op::SetProperty(name) => {
+                    let value = self.pop();
+                    let object = self.pop();
+                    let result = set_property(object, &chunk.tokens[name as usize], value)?;
+                    self.push(result);
+                }
+                Op::GetIndex(bracket, count) => {
+                    let indices = self.pop_many(count as usize);
+                    let object = self.pop();
+                    let value = get_index(object, &indices, &chunk.tokens[bracket as usize])?;
+                    self.push(value);
+                }
+                Op::SetIndex(bracket, count) => {
+                    let value = self.pop();
+                    let indices = self.pop_many(count as usize);
+                    let object = self.pop();
+                    let result =
+                        set_index(object, &indices, value, &chunk.tokens[bracket as usize])?;
+                    self.push(result);
+                }
+                Op::Binary(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let operator = &chunk.tokens[operator as usize];
+                    let result = match (&left, &right) {
+                        (LiteralValue::Number(x), LiteralValue::Number(y)) => {
+                            match number_binary(*x, operator.token_type, *y) {
+                                Some(result) => result,
+                                None => binary_op(left, operator, right)?,
+                            }
+                        }
+                        _ => binary_op(left, operator, right)?,
+                    };
+                    self.push(result);
+                }
+                Op::Unary(operator) => {
+                    let right = self.pop();
+                    let result = unary_op(&chunk.tokens[operator as usize], right)?;
+                    self.push(result);
+                }
+                Op::Jump(target) | Op::Loop(target) => ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if self.pop().is_truthy()? != LiteralValue::True {
+                        ip = target as usize;
+                    }
+                }
+                Op::AndJump(target) => {
+                    if self.peek().is_truthy()? == LiteralValue::False {
+                        self.stack.pop();
+                        self.push(LiteralValue::False);
+                        ip = target as usize;
+                    } else {
+                        self.stack.pop();
+                    }
+                }
+                Op::OrJump(target) => {
+                    if self.peek().is_truthy()? == LiteralValue::True {
+                        ip = target as usize;
+                    } else {
+                        self.stack.pop();
+                    }
+                }
+                Op::Call(count) => {
+                    let callee_slot = self.stack.len() - count as usize - 1;
+                    let callee = self.stack[callee_slot].get();
+                    let line = chunk.spans[ip - 1].line;
+                    if let LiteralValue::Callable(CallableImpl::Compiled(target)) = callee {
+                        callstack::enter(target.proto.name.clone(), line)?;
+                        check_arity(&target, count as usize)?;
+                        if let Some(frame) = self.frames.last_mut() {
+                            frame.ip = ip;
+                        }
+                        self.frames.push(Frame {
+                            closure: target.clone(),
+                            ip: 0,
+                            base: callee_slot,
+                        });
+                        closure = target;
+                        ip = 0;
+                        base = callee_slot;
+                    } else {
+                        let arguments = self.pop_many(count as usize);
+                        let callee = self.pop();
+                        let result = call_value(callee, arguments, line)?;
+                        self.push(result);
+                    }
+                }
+                Op::Closure(index) => {
+                    let proto = chunk.functions[index as usize].clone();
+                    let mut upvalues = vec![];
+                    // A local function that refers to itself captures the slot it is about to fill
+                    let mut own_cell = None;
+                    for capture in &proto.captures {
+                        let cell = if !capture.is_local {
+                            closure.upvalues[capture.index as usize].clone()
+                        } else if base + capture.index as usize == self.stack.len() {
+                            own_cell
+                                .get_or_insert_with(|| heap::variable(LiteralValue::Nil))
+                                .clone()
+                        } else {
+                            self.capture(base + capture.index as usize)
+                        };
+                        upvalues.push(cell);
+                    }
+                    let value = LiteralValue::Callable(CallableImpl::Compiled(heap::closure(Closure {
+                        proto,
+                        upvalues,
+                        globals: closure.globals.clone(),
+                    })));
+                    match own_cell {
+                        Some(cell) => {
+                            *cell.borrow_mut() = value;
+                            self.stack.push(Slot::Cell(cell));
+                        }
+                        None => self.push(value),
+                    }
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Retorno sem chamada");
+                    self.stack.truncate(frame.base);
+                    match self.frames.last() {
+                        None => return Ok(result),
+                        Some(caller) => {
+                            callstack::leave();
+                            closure = caller.closure.clone();
+                            ip = caller.ip;
+                            base = caller.base;
+                            self.push(result);
+                        }
+                    }
+                }
+                Op::Print => {
+                    let line = chunk.spans[ip - 1].line;
+                    let value = to_text(&self.pop(), line)?;
+                    budget::output(value.len() + 1)?;
+                    println!("{value}");
+                }
+                Op::Limpar => {
+                    let value = self.pop();
+                    clear_screen(&value)?;
+                }
+                Op::Tuple(count) => {
+                    let elements = self.pop_many(count as usize);
+                    self.push(LiteralValue::Tuple(elements));
+                }
+                Op::Destructure(count) => {
+                    let value = self.pop();
+                    for element in destructure(&value, count as usize)? {
+                        self.push(element);
+                    }
+                }
+                Op::MakeArray(name, dimensions, element_type) => {
+                    let values = self.pop_many(dimensions as usize * 2);
+                    let bounds = values
+                        .chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect();
+                    let array = make_array(
+                        &chunk.tokens[name as usize],
+                        bounds,
+                        &chunk.tokens[element_type as usize],
+                    )?;
+                    self.push(array);
+                }
+                Op::MakeRange(operator, has_step, inclusive) => {
+                    let step = if has_step {
+                        self.pop()
+                    } else {
+                        LiteralValue::Number(1.0)
+                    };
+                    let end = self.pop();
+                    let start = self.pop();
+                    let range = make_range(
+                        start,
+                        end,
+                        step,
+                        &chunk.tokens[operator as usize],
+                        inclusive,
+                    )?;
+                    self.push(range);
+                }
+                Op::IterNext(slot, exit) => {
+                    let slot = base + slot as usize;
+                    let position = match self.stack[slot + 1].get() {
+                        LiteralValue::Number(x) => x as usize,
+                        _ => 0,
+                    };
+                    match iteration_value(&self.stack[slot].get(), position)? {
+                        None => ip = exit as usize,
+                        Some(value) => {
+                            self.set_slot(slot + 1, LiteralValue::Number(position as f64 + 1.0));
+                            self.set_slot(slot + 2, value);
+                        }
+                    }
+                }
+                Op::TreeStmt(index) => {
+                    let mut interpreter = Interpreter::with_env(closure.globals.clone());
+                    interpreter.interpret(vec![&chunk.statements[index as usize]])?;
+                }
+                Op::TreeExpr(index) => {
+                    let value =
+                        chunk.expressions[index as usize].evaluate(closure.globals.clone())?;
+                    self.push(value);
+                }
+            }
+        }
+    }
+}
+
+/// Arithmetic and comparisons between two numbers, without going through `binary_op`
+fn number_binary(x: f64, operator: TokenType, y: f64) -> Option<LiteralValue> {
+    let result = match operator {
+        TokenType::Plus => LiteralValue::Number(x + y),
+        TokenType::Minus => LiteralValue::Number(x - y),
+        TokenType::Star => LiteralValue::Number(x * y),
+        TokenType::Less => LiteralValue::from_bool(x < y),
+        TokenType::LessEqual => LiteralValue::from_bool(x <= y),
+        TokenType::Greater => LiteralValue::from_bool(x > y),
+        TokenType::GreaterEqual => LiteralValue::from_bool(x >= y),
+        TokenType::EqualEqual => LiteralValue::from_bool(x == y),
+        TokenType::BangEqual => LiteralValue::from_bool(x != y),
+        _ => return None,
+    };
+    Some(result)
+}